@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use lambda2ts::CompileOptions;
+
+// a program with many small declarations that refer to each other
+fn wide_program(size: usize) -> String {
+    let mut res = String::from("let D0 x = x;\n");
+    for i in 1..size {
+        let prev = i - 1;
+        res.push_str(&format!(
+            "let D{i} f x = D{prev} (fun y -> f (let g = x y in g y)) (let h = f x in h x);\n"
+        ));
+    }
+    res
+}
+
+// a program with a single declaration whose body is deeply nested
+fn deep_program(size: usize) -> String {
+    let mut res = String::from("let Deep a =\n");
+    for i in 0..size {
+        res.push_str(&format!("  let x{i} = fun y -> a (fun z -> y z x{i}) in\n"));
+    }
+    res.push_str("  a;\n");
+    res
+}
+
+fn compile(input: &str) -> String {
+    lambda2ts::compile(input, &CompileOptions::default())
+        .expect("failed to compile")
+        .code
+}
+
+fn bench_compile(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compile");
+    group.sample_size(10);
+    for size in [100, 1000, 4000] {
+        let input = wide_program(size);
+        group.bench_with_input(BenchmarkId::new("wide", size), &input, |b, input| {
+            b.iter(|| compile(input))
+        });
+    }
+    for size in [50, 200, 800] {
+        let input = deep_program(size);
+        group.bench_with_input(BenchmarkId::new("deep", size), &input, |b, input| {
+            b.iter(|| compile(input))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_compile);
+criterion_main!(benches);
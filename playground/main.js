@@ -0,0 +1,26 @@
+// built with `wasm-pack build --target web --out-dir playground/pkg -- --features wasm`
+import init, { compile } from "./pkg/lambda2ts.js";
+
+const source = document.getElementById("source");
+const foldConstants = document.getElementById("fold-constants");
+const output = document.getElementById("output");
+const diagnostics = document.getElementById("diagnostics");
+
+function update() {
+  const result = compile(source.value, foldConstants.checked);
+  output.textContent = result.code ?? "";
+  diagnostics.replaceChildren(
+    ...result.diagnostics.map((diagnostic) => {
+      const item = document.createElement("li");
+      const position = diagnostic.line === undefined ? "" : `${diagnostic.line}:${diagnostic.column}: `;
+      item.textContent = position + diagnostic.message;
+      return item;
+    }),
+  );
+  result.free();
+}
+
+await init();
+source.addEventListener("input", update);
+foldConstants.addEventListener("change", update);
+update();
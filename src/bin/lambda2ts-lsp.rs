@@ -0,0 +1,750 @@
+//! A language server for lambda2ts sources, which communicates over the standard input and output.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+};
+
+use lambda2ts::{
+    lambda::{Statement, Term},
+    parser::{self, Span},
+    tools::{self, Analysis},
+    CompileOptions,
+};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as NotificationTrait, PublishDiagnostics,
+    },
+    request::{
+        DocumentSymbolRequest, GotoDefinition, HoverRequest, Rename, Request as RequestTrait,
+    },
+    Diagnostic, DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse, GotoDefinitionResponse,
+    Hover, HoverContents, HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf,
+    Position, PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+    WorkspaceEdit,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+// LSP positions count columns in UTF-16 code units
+fn to_position(source: &str, offset: usize) -> Position {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+fn to_offset(source: &str, position: Position) -> Option<usize> {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        line_start += source[line_start..].find('\n')? + 1;
+    }
+    let mut column = 0;
+    for (i, c) in source[line_start..].char_indices() {
+        if column >= position.character as usize || c == '\n' {
+            return Some(line_start + i);
+        }
+        column += c.len_utf16();
+    }
+    Some(source.len())
+}
+
+fn to_range(source: &str, span: Span) -> Range {
+    Range {
+        start: to_position(source, span.start),
+        end: to_position(source, span.end),
+    }
+}
+
+// whether renaming the names at the spans, which are in increasing order, keeps every name bound where it was.
+// it is not when the new name is captured by a binder around a renamed name, when it is already used in the scope
+// of the renamed binder, or when it is already declared at the top level
+fn keeps_bindings(source: &str, analysis: &Analysis, spans: &[Span], new_name: &str) -> bool {
+    let mut renamed = String::new();
+    let mut last = 0;
+    for span in spans {
+        renamed.push_str(&source[last..span.start]);
+        renamed.push_str(new_name);
+        last = span.end;
+    }
+    renamed.push_str(&source[last..]);
+    let Ok(renamed_analysis) = tools::analyze(&renamed) else {
+        return false;
+    };
+    // the offset in the renamed source of an offset that is not inside a renamed name
+    let moved = |offset: usize| {
+        let before = spans.iter().filter(|span| span.start < offset);
+        before.fold(offset, |offset, span| {
+            offset + new_name.len() - (span.end - span.start)
+        })
+    };
+    let bindings = |occurrences: &[tools::Occurrence], moved: &dyn Fn(usize) -> usize| {
+        occurrences
+            .iter()
+            .map(|occurrence| {
+                let binder = occurrence.binder.map(|binder| moved(binder.start));
+                (moved(occurrence.span.start), binder)
+            })
+            .collect::<Vec<_>>()
+    };
+    bindings(&analysis.occurrences, &moved)
+        == bindings(&renamed_analysis.occurrences, &|offset| offset)
+}
+
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    // the document and its analysis, which is `None` if the document does not parse
+    fn analyze(&self, uri: &Url) -> Option<(&str, Analysis)> {
+        let source = self.documents.get(uri)?;
+        let analysis = tools::analyze(source).ok()?;
+        Some((source, analysis))
+    }
+
+    fn diagnostics(&self, uri: &Url) -> PublishDiagnosticsParams {
+        let source = self.documents.get(uri).map_or("", String::as_str);
+        let diagnostics = match tools::analyze(source) {
+            Ok(analysis) => analysis
+                .unbound()
+                .map(|occurrence| Diagnostic {
+                    range: to_range(source, occurrence.span),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    source: Some(String::from("lambda2ts")),
+                    message: format!("unbound name `{}`", occurrence.name),
+                    ..Diagnostic::default()
+                })
+                .collect(),
+            Err(diagnostic) => {
+                let position = diagnostic.position.map_or(Position::default(), |position| {
+                    to_position(source, position.offset)
+                });
+                vec![Diagnostic {
+                    range: Range {
+                        start: position,
+                        end: position,
+                    },
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some(String::from("lambda2ts")),
+                    message: diagnostic.message,
+                    ..Diagnostic::default()
+                }]
+            }
+        };
+        PublishDiagnosticsParams {
+            uri: uri.clone(),
+            diagnostics,
+            version: None,
+        }
+    }
+
+    // the binder of the name at the position, with the document and its analysis
+    fn resolve(&self, params: &TextDocumentPositionParams) -> Option<(&str, Analysis, Span)> {
+        let (source, analysis) = self.analyze(&params.text_document.uri)?;
+        let offset = to_offset(source, params.position)?;
+        let binder = analysis.occurrence_at(offset)?.binder?;
+        Some((source, analysis, binder))
+    }
+
+    fn definition(&self, params: TextDocumentPositionParams) -> Option<GotoDefinitionResponse> {
+        let (source, _, binder) = self.resolve(&params)?;
+        Some(GotoDefinitionResponse::Scalar(Location {
+            uri: params.text_document.uri,
+            range: to_range(source, binder),
+        }))
+    }
+
+    // shows the generated code of a top-level name, which is the part of the code of the whole program
+    // that comes from its declaration, so that it is generated as it is in the program
+    fn hover(&self, params: TextDocumentPositionParams) -> Option<Hover> {
+        let (source, analysis, binder) = self.resolve(&params)?;
+        let decl = analysis.declaration(binder)?;
+        let options = CompileOptions {
+            source_map: true,
+            ..CompileOptions::default()
+        };
+        let output = lambda2ts::compile(source, &options).ok()?;
+        let start = to_position(source, decl.span.start);
+        let end = to_position(source, decl.span.end);
+        // the types of a declaration begin with a mapping into it, and are preceded by their doc comments
+        let from_decl: BTreeSet<usize> = output
+            .source_map?
+            .mappings
+            .iter()
+            .filter(|mapping| {
+                let position = Position {
+                    line: mapping.source_line as u32,
+                    character: mapping.source_column as u32,
+                };
+                mapping.generated_column == 0 && start <= position && position < end
+            })
+            .map(|mapping| mapping.generated_line)
+            .collect();
+        let lines: Vec<&str> = output.code.lines().collect();
+        let mut code = String::new();
+        for i in from_decl {
+            let comment = lines[..i]
+                .iter()
+                .rev()
+                .take_while(|line| line.starts_with("/**") || line.starts_with(" *"))
+                .count();
+            for line in &lines[i - comment..=i] {
+                code.push_str(line);
+                code.push('\n');
+            }
+        }
+        let offset = to_offset(source, params.position)?;
+        let occurrence = analysis.occurrence_at(offset)?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```typescript\n{code}```"),
+            }),
+            range: Some(to_range(source, occurrence.span)),
+        })
+    }
+
+    fn document_symbols(&self, uri: &Url) -> Option<DocumentSymbolResponse> {
+        let (source, analysis) = self.analyze(uri)?;
+        let symbols = analysis
+            .declarations
+            .iter()
+            .map(|decl| {
+                let Statement::Declaration {
+                    name,
+                    value,
+                    attrs: _,
+                    doc: _,
+                } = &decl.stmt;
+                let kind = match value.as_ref() {
+                    Term::Abs { param: _, body: _ } => SymbolKind::FUNCTION,
+                    _ => SymbolKind::CONSTANT,
+                };
+                #[allow(deprecated)]
+                DocumentSymbol {
+                    name: name.to_string(),
+                    detail: None,
+                    kind,
+                    tags: None,
+                    deprecated: None,
+                    range: to_range(source, decl.span),
+                    selection_range: to_range(source, decl.name_span),
+                    children: None,
+                }
+            })
+            .collect();
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+
+    fn rename(
+        &self,
+        params: TextDocumentPositionParams,
+        new_name: &str,
+    ) -> Result<Option<WorkspaceEdit>, String> {
+        let is_name = matches!(
+            parser::parse_term(new_name),
+            Ok(Term::Var { name }) if name.as_str() == new_name
+        );
+        if !is_name {
+            return Err(format!("`{new_name}` is not a valid name"));
+        }
+        let Some((source, analysis, binder)) = self.resolve(&params) else {
+            return Ok(None);
+        };
+        let spans: Vec<Span> = analysis
+            .references(binder)
+            .map(|occurrence| occurrence.span)
+            .collect();
+        if !keeps_bindings(source, &analysis, &spans, new_name) {
+            return Err(format!(
+                "`{new_name}` clashes with a name in the scope of `{}`",
+                &source[binder.start..binder.end]
+            ));
+        }
+        let edits = spans
+            .iter()
+            .map(|&span| TextEdit {
+                range: to_range(source, span),
+                new_text: String::from(new_name),
+            })
+            .collect();
+        Ok(Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(params.text_document.uri, edits)])),
+            ..WorkspaceEdit::default()
+        }))
+    }
+
+    fn handle_request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            GotoDefinition::METHOD => respond::<GotoDefinition>(request, |params| {
+                Ok(self.definition(params.text_document_position_params))
+            }),
+            HoverRequest::METHOD => respond::<HoverRequest>(request, |params| {
+                Ok(self.hover(params.text_document_position_params))
+            }),
+            DocumentSymbolRequest::METHOD => respond::<DocumentSymbolRequest>(request, |params| {
+                Ok(self.document_symbols(&params.text_document.uri))
+            }),
+            Rename::METHOD => respond::<Rename>(request, |params| {
+                self.rename(params.text_document_position, &params.new_name)
+            }),
+            _ => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unknown method: {}", request.method),
+            ),
+        }
+    }
+
+    // returns the diagnostics to publish
+    fn handle_notification(&mut self, notification: Notification) -> Option<Notification> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = parse_params::<DidOpenTextDocument>(notification.params)?;
+                let document = params.text_document;
+                self.documents.insert(document.uri.clone(), document.text);
+                document.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let params = parse_params::<DidChangeTextDocument>(notification.params)?;
+                // the whole text is sent on every change
+                let text = params.content_changes.into_iter().last()?.text;
+                self.documents
+                    .insert(params.text_document.uri.clone(), text);
+                params.text_document.uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = parse_params::<DidCloseTextDocument>(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                params.text_document.uri
+            }
+            _ => return None,
+        };
+        Some(Notification::new(
+            String::from(PublishDiagnostics::METHOD),
+            self.diagnostics(&uri),
+        ))
+    }
+}
+
+fn parse_params<N: NotificationTrait>(params: serde_json::Value) -> Option<N::Params>
+where
+    N::Params: DeserializeOwned,
+{
+    serde_json::from_value(params).ok()
+}
+
+fn respond<R: RequestTrait>(
+    request: Request,
+    handle: impl FnOnce(R::Params) -> Result<R::Result, String>,
+) -> Response
+where
+    R::Params: DeserializeOwned,
+    R::Result: Serialize,
+{
+    let params = match serde_json::from_value(request.params) {
+        Ok(params) => params,
+        Err(err) => {
+            return Response::new_err(request.id, ErrorCode::InvalidParams as i32, err.to_string())
+        }
+    };
+    match handle(params) {
+        Ok(result) => Response::new_ok(request.id, result),
+        Err(message) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, message),
+    }
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    }
+}
+
+// serves until the client shuts the server down
+fn serve(connection: &Connection) -> Result<(), BoxError> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    let mut server = Server::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                let response = server.handle_request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(diagnostics) = server.handle_notification(notification) {
+                    connection.sender.send(Message::Notification(diagnostics))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), BoxError> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use indoc::indoc;
+    use lsp_server::RequestId;
+    use lsp_types::{
+        notification::{Exit, Initialized},
+        request::{Initialize, Shutdown},
+        DidChangeTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbolParams,
+        GotoDefinitionParams, HoverParams, InitializeParams, InitializedParams, RenameParams,
+        TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+        VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+
+    // a client talking to a server running on another thread
+    struct Client {
+        connection: Connection,
+        server: Option<thread::JoinHandle<()>>,
+        next_id: i32,
+    }
+
+    impl Client {
+        fn start() -> Client {
+            let (server, connection) = Connection::memory();
+            let server = thread::spawn(move || serve(&server).unwrap());
+            let mut client = Client {
+                connection,
+                server: Some(server),
+                next_id: 0,
+            };
+            let result = client.request::<Initialize>(InitializeParams::default());
+            assert_eq!(result.capabilities, capabilities());
+            client.notify::<Initialized>(InitializedParams {});
+            client
+        }
+
+        fn request<R: RequestTrait>(&mut self, params: R::Params) -> R::Result
+        where
+            R::Params: Serialize,
+            R::Result: DeserializeOwned,
+        {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), String::from(R::METHOD), params);
+            self.connection.sender.send(request.into()).unwrap();
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) => {
+                    assert_eq!(response.id, id);
+                    assert!(response.error.is_none());
+                    serde_json::from_value(response.result.unwrap()).unwrap()
+                }
+                message => panic!("unexpected message: {message:?}"),
+            }
+        }
+
+        fn request_err<R: RequestTrait>(&mut self, params: R::Params) -> String
+        where
+            R::Params: Serialize,
+        {
+            self.next_id += 1;
+            let request = Request::new(self.next_id.into(), String::from(R::METHOD), params);
+            self.connection.sender.send(request.into()).unwrap();
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) => response.error.unwrap().message,
+                message => panic!("unexpected message: {message:?}"),
+            }
+        }
+
+        fn notify<N: NotificationTrait>(&self, params: N::Params)
+        where
+            N::Params: Serialize,
+        {
+            let notification = Notification::new(String::from(N::METHOD), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        fn diagnostics(&self) -> Vec<Diagnostic> {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Notification(notification) => {
+                    assert_eq!(notification.method, PublishDiagnostics::METHOD);
+                    let params: PublishDiagnosticsParams =
+                        serde_json::from_value(notification.params).unwrap();
+                    params.diagnostics
+                }
+                message => panic!("unexpected message: {message:?}"),
+            }
+        }
+
+        fn shutdown(mut self) {
+            self.request::<Shutdown>(());
+            self.notify::<Exit>(());
+            self.server.take().unwrap().join().unwrap();
+        }
+    }
+
+    fn uri() -> Url {
+        Url::parse("file:///test.ml").unwrap()
+    }
+
+    fn at(line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri() },
+            position: Position { line, character },
+        }
+    }
+
+    fn range(line: u32, start: u32, end: u32) -> Range {
+        Range {
+            start: Position {
+                line,
+                character: start,
+            },
+            end: Position {
+                line,
+                character: end,
+            },
+        }
+    }
+
+    const SOURCE: &str = indoc! {"
+        (* λ *) let Id x = x;
+        let Apply f x = f x;
+        let Main = Apply Id y
+    "};
+
+    fn open(client: &Client, text: &str) {
+        client.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri(),
+                language_id: String::from("ocaml"),
+                version: 1,
+                text: String::from(text),
+            },
+        });
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let client = Client::start();
+        open(&client, SOURCE);
+        let diagnostics = client.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "unbound name `y`");
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(diagnostics[0].range, range(2, 20, 21));
+
+        client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri(),
+                version: 2,
+            },
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: String::from("let Id x = x;\nlet = x"),
+            }],
+        });
+        let diagnostics = client.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "syntax error near `=`");
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[0].range, range(1, 4, 4));
+        client.shutdown();
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut client = Client::start();
+        open(&client, SOURCE);
+        client.diagnostics();
+
+        // `Id` in `Apply Id y`, after a comment with a character outside of ASCII
+        let definition = client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: at(2, 17),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        assert_eq!(
+            definition,
+            Some(GotoDefinitionResponse::Scalar(Location {
+                uri: uri(),
+                range: range(0, 12, 14),
+            }))
+        );
+        // `x` in `f x`
+        let definition = client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: at(1, 18),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        assert_eq!(
+            definition,
+            Some(GotoDefinitionResponse::Scalar(Location {
+                uri: uri(),
+                range: range(1, 12, 13),
+            }))
+        );
+
+        let hover = client
+            .request::<HoverRequest>(HoverParams {
+                text_document_position_params: at(2, 12),
+                work_done_progress_params: Default::default(),
+            })
+            .unwrap();
+        let expected = indoc! {r#"
+            ```typescript
+            interface Apply extends Fun { ret: Apply$1<this["arg"]> }
+            interface Apply$1<f> extends Fun { ret: Apply$2<f, this["arg"]> }
+            type Apply$2<f, x> = App<f, x>;
+            ```"#};
+        assert_eq!(
+            hover.contents,
+            HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: String::from(expected),
+            })
+        );
+        assert_eq!(hover.range, Some(range(2, 11, 16)));
+        // calls of known functions are generated as in the program
+        let hover = client
+            .request::<HoverRequest>(HoverParams {
+                text_document_position_params: at(2, 5),
+                work_done_progress_params: Default::default(),
+            })
+            .unwrap();
+        assert_eq!(
+            hover.contents,
+            HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: String::from("```typescript\ntype Main = Apply$2<Id, y>;\n```"),
+            })
+        );
+        // local names have no hover
+        let hover = client.request::<HoverRequest>(HoverParams {
+            text_document_position_params: at(1, 16),
+            work_done_progress_params: Default::default(),
+        });
+        assert_eq!(hover, None);
+
+        let symbols = client
+            .request::<DocumentSymbolRequest>(DocumentSymbolParams {
+                text_document: TextDocumentIdentifier { uri: uri() },
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .unwrap();
+        let DocumentSymbolResponse::Nested(symbols) = symbols else {
+            panic!("flat symbols");
+        };
+        let symbols: Vec<_> = symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.selection_range))
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("Id", SymbolKind::FUNCTION, range(0, 12, 14)),
+                ("Apply", SymbolKind::FUNCTION, range(1, 4, 9)),
+                ("Main", SymbolKind::CONSTANT, range(2, 4, 8)),
+            ]
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn test_hover_folded() {
+        let mut client = Client::start();
+        let source = indoc! {"
+            (** The identity. *)
+            let[@eval] Id = (fun x y -> y) (fun z -> z) (fun x -> x);
+            let Main = Id Id
+        "};
+        open(&client, source);
+        client.diagnostics();
+
+        let hover = client
+            .request::<HoverRequest>(HoverParams {
+                text_document_position_params: at(1, 12),
+                work_done_progress_params: Default::default(),
+            })
+            .unwrap();
+        let expected = indoc! {r#"
+            ```typescript
+            /** The identity. */
+            interface Id extends Fun { ret: Id$1<this["arg"]> }
+            /** Helper of {@link Id}. */
+            type Id$1<x> = x;
+            ```"#};
+        assert_eq!(
+            hover.contents,
+            HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: String::from(expected),
+            })
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut client = Client::start();
+        open(&client, SOURCE);
+        client.diagnostics();
+
+        let rename = |position, new_name: &str| RenameParams {
+            text_document_position: position,
+            new_name: String::from(new_name),
+            work_done_progress_params: Default::default(),
+        };
+        let edit = client.request::<Rename>(rename(at(0, 13), "I")).unwrap();
+        let edits = &edit.changes.unwrap()[&uri()];
+        let ranges: Vec<_> = edits.iter().map(|edit| edit.range).collect();
+        assert_eq!(ranges, [range(0, 12, 14), range(2, 17, 19)]);
+        assert!(edits.iter().all(|edit| edit.new_text == "I"));
+
+        // only the parameter of `Apply` is renamed
+        let edit = client.request::<Rename>(rename(at(1, 18), "y")).unwrap();
+        let ranges: Vec<_> = edit.changes.unwrap()[&uri()]
+            .iter()
+            .map(|edit| edit.range)
+            .collect();
+        assert_eq!(ranges, [range(1, 12, 13), range(1, 18, 19)]);
+
+        let err = client.request_err::<Rename>(rename(at(0, 13), "fun"));
+        assert_eq!(err, "`fun` is not a valid name");
+        // `f x` would become `f f`
+        let err = client.request_err::<Rename>(rename(at(1, 18), "f"));
+        assert_eq!(err, "`f` clashes with a name in the scope of `x`");
+        // `f` would be shadowed by the parameter `x`
+        let err = client.request_err::<Rename>(rename(at(1, 10), "x"));
+        assert_eq!(err, "`x` clashes with a name in the scope of `f`");
+        // `Apply` is already declared
+        let err = client.request_err::<Rename>(rename(at(0, 13), "Apply"));
+        assert_eq!(err, "`Apply` clashes with a name in the scope of `Id`");
+        // the free `y` in `Main` would refer to `Id`
+        let err = client.request_err::<Rename>(rename(at(2, 17), "y"));
+        assert_eq!(err, "`y` clashes with a name in the scope of `Id`");
+        client.shutdown();
+    }
+}
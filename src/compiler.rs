@@ -1,4 +1,18 @@
-pub mod generator;
-pub mod lambda;
-pub mod parser;
-pub mod transformer;
+pub(crate) mod analysis;
+pub(crate) mod ast_json;
+pub(crate) mod core;
+pub(crate) mod cpp;
+pub(crate) mod diagnostics;
+pub(crate) mod formatter;
+pub(crate) mod generator;
+pub(crate) mod lambda;
+pub(crate) mod lexer;
+pub(crate) mod parser;
+pub(crate) mod pipeline;
+pub(crate) mod printer;
+pub(crate) mod repl;
+pub(crate) mod ski;
+pub(crate) mod source_map;
+pub(crate) mod symbol;
+pub(crate) mod trampoline;
+pub(crate) mod transformer;
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use super::{
+    diagnostics::Diagnostic,
+    lambda::{Statement, Term, Visitor},
+    lexer::{self, Token, TokenKind},
+    parser,
+    symbol::Symbol,
+};
+
+pub use super::parser::Span;
+
+/// A top-level declaration with its location.
+#[derive(Debug)]
+pub struct Declaration {
+    pub stmt: Statement,
+    /// The statement without the terminating `;`.
+    pub span: Span,
+    pub name_span: Span,
+}
+
+impl Declaration {
+    pub fn name(&self) -> Symbol {
+        match &self.stmt {
+            Statement::Declaration {
+                name,
+                value: _,
+                attrs: _,
+                doc: _,
+            } => *name,
+        }
+    }
+}
+
+/// An occurrence of a name, either where it is bound or where it is used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Occurrence {
+    pub name: Symbol,
+    pub span: Span,
+    /// Where the name is bound, which is `None` if the name is unbound.
+    pub binder: Option<Span>,
+}
+
+/// The declarations of a program and the occurrences of names in it, which are in the order of the source.
+#[derive(Debug)]
+pub struct Analysis {
+    pub declarations: Vec<Declaration>,
+    pub occurrences: Vec<Occurrence>,
+}
+
+// the identifiers among the tokens of a statement, except those of attributes, which are in the same order as
+// the names in the statement are walked
+fn identifiers(tokens: &[Token]) -> Vec<Span> {
+    let mut res = Vec::new();
+    let mut in_attribute = false;
+    for token in tokens {
+        match token.kind {
+            TokenKind::AttributeStart => in_attribute = true,
+            TokenKind::RightBracket => in_attribute = false,
+            TokenKind::Identifier if !in_attribute => res.push(token.span),
+            _ => {}
+        }
+    }
+    res
+}
+
+// resolves the names of a declaration, whose binders are taken from the identifiers as they are entered
+struct Resolver<'a, I> {
+    identifiers: I,
+    globals: &'a HashMap<Symbol, Span>,
+    // binders entered but whose scopes have not begun yet, e.g. the name of `let` while its value is walked
+    pending: Vec<Span>,
+    scopes: Vec<(Symbol, Span)>,
+    occurrences: Vec<Occurrence>,
+}
+
+impl<I: Iterator<Item = Span>> Resolver<'_, I> {
+    fn next_span(&mut self) -> Span {
+        self.identifiers
+            .next()
+            .expect("identifiers do not match the statement")
+    }
+}
+
+impl<I: Iterator<Item = Span>> Visitor<'_> for Resolver<'_, I> {
+    fn enter(&mut self, term: &Term) {
+        match term {
+            Term::Abs {
+                param: name,
+                body: _,
+            }
+            | Term::Let {
+                name,
+                value: _,
+                body: _,
+            } => {
+                let span = self.next_span();
+                self.pending.push(span);
+                self.occurrences.push(Occurrence {
+                    name: *name,
+                    span,
+                    binder: Some(span),
+                });
+            }
+            Term::Var { name: _ } | Term::App { func: _, arg: _ } => {}
+        }
+    }
+
+    fn var(&mut self, name: Symbol) {
+        let span = self.next_span();
+        let binder = self
+            .scopes
+            .iter()
+            .rev()
+            .find(|(bound, _)| *bound == name)
+            .map(|(_, binder)| *binder)
+            .or_else(|| self.globals.get(&name).copied());
+        self.occurrences.push(Occurrence { name, span, binder });
+    }
+
+    fn bind(&mut self, name: Symbol) {
+        let binder = self.pending.pop().expect("binder not entered");
+        self.scopes.push((name, binder));
+    }
+
+    fn unbind(&mut self, _name: Symbol) {
+        self.scopes.pop();
+    }
+}
+
+/// Parses a program and resolves the names in it.
+/// Top-level names are visible in every declaration, and a name declared twice refers to the first declaration.
+pub fn analyze(source: &str) -> Result<Analysis, Diagnostic> {
+    let (items, _) =
+        parser::parse_items(source).map_err(|err| Diagnostic::from_parse_error(source, &err))?;
+    let tokens =
+        lexer::tokenize(source).map_err(|err| Diagnostic::from_parse_error(source, &err))?;
+    let mut declarations = Vec::new();
+    let mut statement_identifiers = Vec::new();
+    for item in items {
+        let offset = item.source.as_ptr() as usize - source.as_ptr() as usize;
+        let span = Span {
+            start: offset,
+            end: offset + item.source.len(),
+        };
+        let first = tokens.partition_point(|token| token.span.start < span.start);
+        let last = tokens.partition_point(|token| token.span.start < span.end);
+        let mut spans = identifiers(&tokens[first..last]).into_iter();
+        let name_span = spans.next().expect("declaration without a name");
+        declarations.push(Declaration {
+            stmt: item.stmt,
+            span,
+            name_span,
+        });
+        statement_identifiers.push(spans);
+    }
+    let mut globals = HashMap::new();
+    for decl in &declarations {
+        globals.entry(decl.name()).or_insert(decl.name_span);
+    }
+    let mut occurrences = Vec::new();
+    for (decl, spans) in declarations.iter().zip(statement_identifiers) {
+        occurrences.push(Occurrence {
+            name: decl.name(),
+            span: decl.name_span,
+            binder: globals.get(&decl.name()).copied(),
+        });
+        let Statement::Declaration {
+            name: _,
+            value,
+            attrs: _,
+            doc: _,
+        } = &decl.stmt;
+        let mut resolver = Resolver {
+            identifiers: spans,
+            globals: &globals,
+            pending: Vec::new(),
+            scopes: Vec::new(),
+            occurrences: Vec::new(),
+        };
+        value.walk(&mut resolver);
+        occurrences.extend(resolver.occurrences);
+    }
+    Ok(Analysis {
+        declarations,
+        occurrences,
+    })
+}
+
+impl Analysis {
+    /// Returns the occurrence of a name at the offset.
+    pub fn occurrence_at(&self, offset: usize) -> Option<&Occurrence> {
+        // if the offset is between two names, the one starting at the offset is preferred
+        let i = self
+            .occurrences
+            .partition_point(|occurrence| occurrence.span.start <= offset);
+        self.occurrences[..i]
+            .last()
+            .filter(|occurrence| occurrence.span.touches(offset))
+    }
+
+    /// Returns the occurrences of the name bound at the binder, including the binder itself.
+    pub fn references(&self, binder: Span) -> impl Iterator<Item = &Occurrence> {
+        self.occurrences
+            .iter()
+            .filter(move |occurrence| occurrence.binder == Some(binder))
+    }
+
+    /// Returns the top-level declaration of the name bound at the binder, if it is a top-level name.
+    pub fn declaration(&self, binder: Span) -> Option<&Declaration> {
+        self.declarations
+            .iter()
+            .find(|decl| decl.name_span == binder)
+    }
+
+    /// Returns the occurrences of names that are not bound anywhere.
+    pub fn unbound(&self) -> impl Iterator<Item = &Occurrence> {
+        self.occurrences
+            .iter()
+            .filter(|occurrence| occurrence.binder.is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    fn span_of(source: &str, text: &str, nth: usize) -> Span {
+        let start = source
+            .match_indices(text)
+            .nth(nth)
+            .map(|(start, _)| start)
+            .unwrap();
+        Span {
+            start,
+            end: start + text.len(),
+        }
+    }
+
+    #[test]
+    fn test_analyze() {
+        let source = indoc! {"
+            (* combinators *)
+            let [@eval] Id x = x;
+            let K x y = let z = x in (* y (* x *) *) fun y -> z y w;
+            let Self = K Id -- x
+              Self
+        "};
+        let analysis = analyze(source).unwrap();
+        assert_eq!(analysis.declarations.len(), 3);
+        assert_eq!(analysis.declarations[0].name_span, span_of(source, "Id", 0));
+        assert_eq!(
+            &source[analysis.declarations[1].span.start..analysis.declarations[1].span.end],
+            "let K x y = let z = x in (* y (* x *) *) fun y -> z y w"
+        );
+
+        let names: Vec<_> = analysis
+            .occurrences
+            .iter()
+            .map(|occurrence| &source[occurrence.span.start..occurrence.span.end])
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Id", "x", "x", "K", "x", "y", "z", "x", "y", "z", "y", "w", "Self", "K", "Id",
+                "Self"
+            ]
+        );
+
+        // the inner `y` shadows the parameter
+        let inner_y = span_of(source, "y", 2);
+        let occurrence = analysis
+            .occurrence_at(span_of(source, "y", 3).start)
+            .unwrap();
+        assert_eq!(occurrence.binder, Some(inner_y));
+        let param_y = span_of(source, "y", 0);
+        assert_eq!(analysis.references(param_y).count(), 1);
+
+        let id = span_of(source, "Id", 0);
+        let references: Vec<_> = analysis.references(id).map(|occ| occ.span).collect();
+        assert_eq!(references, [id, span_of(source, "Id", 1)]);
+        assert_eq!(analysis.declaration(id).unwrap().name(), Symbol::from("Id"));
+        assert!(analysis.declaration(span_of(source, "z", 0)).is_none());
+
+        let unbound: Vec<_> = analysis.unbound().map(|occ| occ.name).collect();
+        assert_eq!(unbound, [Symbol::from("w")]);
+    }
+
+    #[test]
+    fn test_occurrence_at() {
+        let source = "let F x = x x";
+        let analysis = analyze(source).unwrap();
+        let at = |offset| analysis.occurrence_at(offset).map(|occ| occ.span.start);
+        assert_eq!(at(0), None);
+        assert_eq!(at(4), Some(4));
+        assert_eq!(at(5), Some(4));
+        assert_eq!(at(11), Some(10));
+        assert_eq!(at(12), Some(12));
+        assert_eq!(at(13), Some(12));
+    }
+
+    #[test]
+    fn test_analyze_error() {
+        let err = analyze("let F = x;\nlet = x").unwrap_err();
+        assert_eq!(err.to_string(), "2:5: syntax error near `=`");
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::lambda::Statement;
+
+/// Version of the JSON representation of programs.
+/// It is incremented whenever the representation of `Term` or `Statement` changes incompatibly.
+pub const VERSION: u32 = 1;
+
+// a program with its version, e.g.
+// {"version":1,"program":[{"declaration":{"name":"Id","value":{"abs":{"param":"x","body":{"var":{"name":"x"}}}},"attrs":[]}}]}
+#[derive(Serialize)]
+struct Document<'a> {
+    version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pass: Option<&'a str>,
+    program: &'a [Statement],
+}
+
+#[derive(Deserialize)]
+struct OwnedDocument {
+    version: u32,
+    program: Vec<Statement>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Json(serde_json::Error),
+    Version(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Json(err) => write!(f, "invalid AST: {err}"),
+            Error::Version(version) => {
+                write!(f, "unsupported AST version: {version} (expected {VERSION})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn to_json(document: &Document) -> String {
+    serde_json::to_string(document).expect("failed to serialize")
+}
+
+/// Writes a program as JSON.
+pub fn write_program(program: &[Statement]) -> String {
+    to_json(&Document {
+        version: VERSION,
+        pass: None,
+        program,
+    })
+}
+
+/// Writes a program after a pass as JSON, which is a program with the name of the pass.
+pub fn write_dump(pass: &str, program: &[Statement]) -> String {
+    to_json(&Document {
+        version: VERSION,
+        pass: Some(pass),
+        program,
+    })
+}
+
+/// Reads a program written as JSON.
+pub fn read_program(input: &str) -> Result<Vec<Statement>, Error> {
+    let mut deserializer = serde_json::Deserializer::from_str(input);
+    // the depth of terms is not limited, as the stack grows with them
+    deserializer.disable_recursion_limit();
+    let document = OwnedDocument::deserialize(&mut deserializer).map_err(Error::Json)?;
+    deserializer.end().map_err(Error::Json)?;
+    if document.version != VERSION {
+        return Err(Error::Version(document.version));
+    }
+    Ok(document.program)
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::compiler::parser::parse;
+
+    #[test]
+    fn test_write_program() {
+        let program = parse("let[@eval] foo = fun x -> let y = x in f y;").unwrap();
+        let expected = concat!(
+            r#"{"version":1,"program":[{"declaration":{"name":"foo","value":"#,
+            r#"{"abs":{"param":"x","body":{"let":{"name":"y","value":{"var":{"name":"x"}},"#,
+            r#""body":{"app":{"func":{"var":{"name":"f"}},"arg":{"var":{"name":"y"}}}}}}}},"#,
+            r#""attrs":["eval"]}}]}"#,
+        );
+        assert_eq!(write_program(&program), expected);
+        assert_eq!(read_program(expected).unwrap(), program);
+    }
+
+    #[test]
+    fn test_write_dump() {
+        let program = parse("let foo = x;").unwrap();
+        let expected = concat!(
+            r#"{"version":1,"pass":"normalize_app","program":"#,
+            r#"[{"declaration":{"name":"foo","value":{"var":{"name":"x"}},"attrs":[]}}]}"#,
+        );
+        assert_eq!(write_dump("normalize_app", &program), expected);
+    }
+
+    #[test]
+    fn test_read_program() {
+        // attributes may be omitted
+        let input = indoc! {r#"
+            {
+              "version": 1,
+              "program": [
+                { "declaration": { "name": "foo", "value": { "var": { "name": "x" } } } }
+              ]
+            }
+        "#};
+        assert_eq!(read_program(input).unwrap(), parse("let foo = x;").unwrap());
+
+        let input = r#"{"version":2,"program":[]}"#;
+        assert!(matches!(read_program(input), Err(Error::Version(2))));
+        let input = r#"{"version":1,"program":[{"declaration":{"name":"foo"}}]}"#;
+        assert!(matches!(read_program(input), Err(Error::Json(_))));
+        let input = r#"{"version":1,"program":[]} []"#;
+        assert!(matches!(read_program(input), Err(Error::Json(_))));
+    }
+
+    #[test]
+    fn test_deep() {
+        let depth = 100_000;
+        let input = format!("let foo = {}x{};", "f (".repeat(depth), ")".repeat(depth));
+        let program = parse(&input).unwrap();
+        let json = write_program(&program);
+        assert_eq!(read_program(&json).unwrap(), program);
+    }
+}
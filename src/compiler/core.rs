@@ -0,0 +1,851 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasherDefault, Hasher},
+    rc::Rc,
+};
+
+use super::{
+    lambda::{Term, Visitor},
+    parser::{Span, Spans},
+    symbol::Symbol,
+};
+
+/// Locally nameless representation of terms.
+/// Bound variables are de Bruijn indices and free variables are names,
+/// so terms can be moved around binders without worrying about capture.
+/// Binders keep the original names only as hints for reconstructing names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Core {
+    BVar {
+        index: usize,
+    },
+    FVar {
+        name: Symbol,
+    },
+    App {
+        func: Node,
+        arg: Node,
+    },
+    Abs {
+        hint: Symbol,
+        body: Node,
+    },
+    Let {
+        hint: Symbol,
+        value: Node,
+        body: Node,
+    },
+}
+
+/// Reference to a core term stored in an `Arena`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Node(u32);
+
+struct NodeData {
+    core: Core,
+    free_vars: Rc<HashSet<Symbol>>,
+    loose_bvars: usize,
+    contains_abs: bool,
+    contains_let: bool,
+    origin: Option<Span>,
+}
+
+/// Store of core terms.
+/// Terms are hash-consed, so structurally equal terms are always shared and compared by their references.
+/// The free variables, the loose bound variables and the kinds of binders of each term
+/// are computed once when it is added, so that analyses and `open`/`close` do not have to traverse the whole term again.
+/// Terms are traversed with explicit stacks, so they may be nested arbitrarily deep.
+///
+/// Each term may have an origin, which is the span of the source that it comes from.
+/// Only terms with the same origin are shared, so that equal terms from different places are traced back
+/// to their own places, while variables have no origins and are shared everywhere.
+/// Terms added without an origin take the smallest span that contains the origins of their children,
+/// so that terms made by passes are traced back to the terms they are made from.
+#[derive(Default)]
+pub struct Arena {
+    nodes: Vec<NodeData>,
+    ids: HashMap<(Core, Option<Span>), Node, BuildHasherDefault<CoreHasher>>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    /// Adds a term whose children are already in the arena, or returns the existing equal one.
+    pub fn add(&mut self, core: Core) -> Node {
+        self.add_with_origin(core, None)
+    }
+
+    /// Adds a term with its origin, or returns the existing equal one with the same origin.
+    pub fn add_with_origin(&mut self, core: Core, origin: Option<Span>) -> Node {
+        let origin = origin.or_else(|| self.compute_origin(&core));
+        if let Some(&node) = self.ids.get(&(core, origin)) {
+            return node;
+        }
+        let free_vars = self.compute_free_vars(&core);
+        let loose_bvars = self.compute_loose_bvars(&core);
+        let (contains_abs, contains_let) = match core {
+            Core::BVar { index: _ } | Core::FVar { name: _ } => (false, false),
+            Core::App { func, arg } => (
+                self.contains_abs(func) || self.contains_abs(arg),
+                self.contains_let(func) || self.contains_let(arg),
+            ),
+            Core::Abs { hint: _, body } => (true, self.contains_let(body)),
+            Core::Let {
+                hint: _,
+                value,
+                body,
+            } => (self.contains_abs(value) || self.contains_abs(body), true),
+        };
+        let node = Node(self.nodes.len() as u32);
+        self.nodes.push(NodeData {
+            core,
+            free_vars,
+            loose_bvars,
+            contains_abs,
+            contains_let,
+            origin,
+        });
+        self.ids.insert((core, origin), node);
+        node
+    }
+
+    /// Adds a term that a pass makes in place of `from`, which comes from the same source.
+    pub fn add_from(&mut self, from: Node, core: Core) -> Node {
+        let origin = self.origin(from);
+        self.add_with_origin(core, origin)
+    }
+
+    pub fn get(&self, node: Node) -> &Core {
+        &self.nodes[node.0 as usize].core
+    }
+
+    pub fn bvar(&mut self, index: usize) -> Node {
+        self.add(Core::BVar { index })
+    }
+
+    pub fn fvar(&mut self, name: impl Into<Symbol>) -> Node {
+        self.add(Core::FVar { name: name.into() })
+    }
+
+    pub fn app(&mut self, func: Node, arg: Node) -> Node {
+        self.add(Core::App { func, arg })
+    }
+
+    pub fn abs(&mut self, hint: impl Into<Symbol>, body: Node) -> Node {
+        self.add(Core::Abs {
+            hint: hint.into(),
+            body,
+        })
+    }
+
+    pub fn let_(&mut self, hint: impl Into<Symbol>, value: Node, body: Node) -> Node {
+        self.add(Core::Let {
+            hint: hint.into(),
+            value,
+            body,
+        })
+    }
+
+    pub fn free_vars(&self, node: Node) -> &HashSet<Symbol> {
+        &self.nodes[node.0 as usize].free_vars
+    }
+
+    pub fn has_free_var(&self, node: Node, name: Symbol) -> bool {
+        self.free_vars(node).contains(&name)
+    }
+
+    /// Returns one plus the largest index of the loose bound variables, or zero if there are none.
+    pub fn loose_bvars(&self, node: Node) -> usize {
+        self.nodes[node.0 as usize].loose_bvars
+    }
+
+    pub fn contains_abs(&self, node: Node) -> bool {
+        self.nodes[node.0 as usize].contains_abs
+    }
+
+    pub fn contains_let(&self, node: Node) -> bool {
+        self.nodes[node.0 as usize].contains_let
+    }
+
+    pub fn origin(&self, node: Node) -> Option<Span> {
+        self.nodes[node.0 as usize].origin
+    }
+
+    pub fn add_term(&mut self, term: &Term) -> Node {
+        from_term(self, term, &[])
+    }
+
+    /// Adds a term whose subterms come from the spans, except its variables, which are shared everywhere.
+    pub fn add_term_with_spans(&mut self, term: &Term, spans: &[Span]) -> Node {
+        from_term(self, term, spans)
+    }
+
+    pub fn to_term(&self, node: Node) -> Term {
+        to_term(self, node)
+    }
+
+    /// The spans of the subterms of the term that `to_term` returns, where subterms without origins
+    /// take the origins of their closest ancestors.
+    /// Returns no spans if the term itself has no origin.
+    pub fn spans(&self, node: Node) -> Spans {
+        spans(self, node)
+    }
+
+    /// Replaces the loose bound variable with index 0 in `node` by `term`.
+    /// `term` must be locally closed.
+    pub fn open(&mut self, node: Node, term: Node) -> Node {
+        open(self, node, term)
+    }
+
+    /// Replaces the free variable `name` in `node` by a loose bound variable with index 0.
+    pub fn close(&mut self, node: Node, name: Symbol) -> Node {
+        close(self, node, name)
+    }
+
+    // computes the free variables from the memoized sets of the children
+    fn compute_free_vars(&self, core: &Core) -> Rc<HashSet<Symbol>> {
+        let of = |node: &Node| &self.nodes[node.0 as usize].free_vars;
+        match core {
+            Core::BVar { index: _ } => Rc::new(HashSet::new()),
+            Core::FVar { name } => Rc::new(HashSet::from([*name])),
+            Core::App { func, arg } => union(of(func), of(arg)),
+            Core::Abs { hint: _, body } => Rc::clone(of(body)),
+            Core::Let {
+                hint: _,
+                value,
+                body,
+            } => union(of(value), of(body)),
+        }
+    }
+
+    fn compute_origin(&self, core: &Core) -> Option<Span> {
+        let union = |a: Option<Span>, b: Option<Span>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.union(b)),
+            (a, b) => a.or(b),
+        };
+        match *core {
+            Core::BVar { index: _ } | Core::FVar { name: _ } => None,
+            Core::App { func, arg } => union(self.origin(func), self.origin(arg)),
+            Core::Abs { hint: _, body } => self.origin(body),
+            Core::Let {
+                hint: _,
+                value,
+                body,
+            } => union(self.origin(value), self.origin(body)),
+        }
+    }
+
+    fn compute_loose_bvars(&self, core: &Core) -> usize {
+        match *core {
+            Core::BVar { index } => index + 1,
+            Core::FVar { name: _ } => 0,
+            Core::App { func, arg } => self.loose_bvars(func).max(self.loose_bvars(arg)),
+            Core::Abs { hint: _, body } => self.loose_bvars(body).saturating_sub(1),
+            Core::Let {
+                hint: _,
+                value,
+                body,
+            } => self
+                .loose_bvars(value)
+                .max(self.loose_bvars(body).saturating_sub(1)),
+        }
+    }
+}
+
+// every added term is hashed for hash-consing, so a cheap multiplicative hash is used instead of SipHash.
+// terms consist only of small integers, so the weaker hash does not matter.
+#[derive(Default)]
+struct CoreHasher(u64);
+
+impl CoreHasher {
+    fn add(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+impl Hasher for CoreHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.add(byte as u64);
+        }
+    }
+
+    fn write_u32(&mut self, word: u32) {
+        self.add(word as u64);
+    }
+
+    fn write_u64(&mut self, word: u64) {
+        self.add(word);
+    }
+
+    fn write_usize(&mut self, word: usize) {
+        self.add(word as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn union(a: &Rc<HashSet<Symbol>>, b: &Rc<HashSet<Symbol>>) -> Rc<HashSet<Symbol>> {
+    if b.is_subset(a) {
+        Rc::clone(a)
+    } else if a.is_subset(b) {
+        Rc::clone(b)
+    } else {
+        Rc::new(a.union(b).cloned().collect())
+    }
+}
+
+// converts a term in post-order, keeping the converted subterms on a stack
+struct FromTerm<'a, I> {
+    arena: &'a mut Arena,
+    spans: I,
+    // depths of the binders of each name in scope, innermost last
+    scope: HashMap<Symbol, Vec<usize>>,
+    depth: usize,
+    results: Vec<Node>,
+}
+
+impl<'a, I: Iterator<Item = &'a Span>> Visitor<'_> for FromTerm<'_, I> {
+    fn var(&mut self, name: Symbol) {
+        let node = match self.scope.get(&name).and_then(|depths| depths.last()) {
+            Some(bound) => self.arena.bvar(self.depth - 1 - bound),
+            None => self.arena.fvar(name),
+        };
+        self.results.push(node);
+    }
+
+    fn bind(&mut self, name: Symbol) {
+        self.scope.entry(name).or_default().push(self.depth);
+        self.depth += 1;
+    }
+
+    fn unbind(&mut self, name: Symbol) {
+        self.depth -= 1;
+        let depths = self.scope.get_mut(&name).unwrap();
+        depths.pop();
+        if depths.is_empty() {
+            self.scope.remove(&name);
+        }
+    }
+
+    fn leave(&mut self, term: &Term) {
+        let origin = self.spans.next().copied();
+        let core = match term {
+            Term::Var { name: _ } => return,
+            Term::App { func: _, arg: _ } => {
+                let arg = self.results.pop().unwrap();
+                let func = self.results.pop().unwrap();
+                Core::App { func, arg }
+            }
+            Term::Abs { param, body: _ } => {
+                let body = self.results.pop().unwrap();
+                Core::Abs { hint: *param, body }
+            }
+            Term::Let {
+                name,
+                value: _,
+                body: _,
+            } => {
+                let body = self.results.pop().unwrap();
+                let value = self.results.pop().unwrap();
+                Core::Let {
+                    hint: *name,
+                    value,
+                    body,
+                }
+            }
+        };
+        let node = self.arena.add_with_origin(core, origin);
+        self.results.push(node);
+    }
+}
+
+fn from_term(arena: &mut Arena, term: &Term, spans: &[Span]) -> Node {
+    let mut from_term = FromTerm {
+        arena,
+        spans: spans.iter(),
+        scope: HashMap::new(),
+        depth: 0,
+        results: Vec::new(),
+    };
+    term.walk(&mut from_term);
+    from_term.results.pop().unwrap()
+}
+
+// binder names are chosen so that they neither capture free variables of the body
+// nor shadow any enclosing binder.
+// if the hint itself is taken, numbered names are tried from `start`.
+// the chosen names are interned, so that fresh symbols displayed the same are told apart in the output
+fn bind_name(
+    arena: &Arena,
+    hint: Symbol,
+    body: Node,
+    scope: &HashSet<Symbol>,
+    start: usize,
+) -> (Symbol, Option<usize>) {
+    let is_used = |name: Symbol| arena.has_free_var(body, name) || scope.contains(&name);
+    let name = Symbol::intern(hint.as_str());
+    if !is_used(name) {
+        return (name, None);
+    }
+    (start..)
+        .map(|i| (Symbol::intern(&format!("{hint}{i}")), Some(i)))
+        .find(|&(name, _)| !is_used(name))
+        .unwrap()
+}
+
+#[derive(Clone, Copy)]
+struct Binder {
+    name: Symbol,
+    // the interned hint and the number appended to it, if any
+    hint: Symbol,
+    number: Option<usize>,
+}
+
+// names of the enclosing binders while converting a term
+#[derive(Default)]
+struct Scope {
+    names: Vec<Symbol>,
+    // binders never shadow each other, so the names are also kept in a set for lookup
+    name_set: HashSet<Symbol>,
+    // numbers of the enclosing binders for each hint, innermost last.
+    // numbering continues from the innermost one, so that deeply nested binders with the same hint
+    // do not try all the numbers taken by their ancestors again.
+    numbers: HashMap<Symbol, Vec<usize>>,
+}
+
+impl Scope {
+    fn binder(&self, arena: &Arena, hint: Symbol, body: Node) -> Binder {
+        let hint = Symbol::intern(hint.as_str());
+        let start = self
+            .numbers
+            .get(&hint)
+            .and_then(|numbers| numbers.last())
+            .map_or(0, |&number| number + 1);
+        let (name, number) = bind_name(arena, hint, body, &self.name_set, start);
+        Binder { name, hint, number }
+    }
+
+    fn bind(&mut self, binder: Binder) {
+        self.names.push(binder.name);
+        self.name_set.insert(binder.name);
+        if let Some(number) = binder.number {
+            self.numbers.entry(binder.hint).or_default().push(number);
+        }
+    }
+
+    fn unbind(&mut self, binder: Binder) {
+        self.names.pop();
+        self.name_set.remove(&binder.name);
+        if binder.number.is_some() {
+            self.numbers.get_mut(&binder.hint).unwrap().pop();
+        }
+    }
+}
+
+fn to_term(arena: &Arena, node: Node) -> Term {
+    enum Task {
+        Visit(Node),
+        Bind(Binder),
+        BuildApp,
+        BuildAbs(Binder),
+        BuildLet(Binder),
+    }
+    let mut scope = Scope::default();
+    let mut results = Vec::new();
+    let mut tasks = vec![Task::Visit(node)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Visit(node) => match *arena.get(node) {
+                Core::BVar { index } => results.push(Term::Var {
+                    name: scope.names[scope.names.len() - 1 - index],
+                }),
+                Core::FVar { name } => results.push(Term::Var { name }),
+                Core::App { func, arg } => {
+                    tasks.push(Task::BuildApp);
+                    tasks.push(Task::Visit(arg));
+                    tasks.push(Task::Visit(func));
+                }
+                Core::Abs { hint, body } => {
+                    let binder = scope.binder(arena, hint, body);
+                    tasks.push(Task::BuildAbs(binder));
+                    tasks.push(Task::Visit(body));
+                    tasks.push(Task::Bind(binder));
+                }
+                Core::Let { hint, value, body } => {
+                    let binder = scope.binder(arena, hint, body);
+                    tasks.push(Task::BuildLet(binder));
+                    tasks.push(Task::Visit(body));
+                    tasks.push(Task::Bind(binder));
+                    tasks.push(Task::Visit(value));
+                }
+            },
+            Task::Bind(binder) => scope.bind(binder),
+            Task::BuildApp => {
+                let arg = results.pop().unwrap();
+                let func = results.pop().unwrap();
+                results.push(Term::App {
+                    func: Rc::new(func),
+                    arg: Rc::new(arg),
+                });
+            }
+            Task::BuildAbs(binder) => {
+                scope.unbind(binder);
+                let body = results.pop().unwrap();
+                results.push(Term::Abs {
+                    param: binder.name,
+                    body: Rc::new(body),
+                });
+            }
+            Task::BuildLet(binder) => {
+                scope.unbind(binder);
+                let body = results.pop().unwrap();
+                let value = results.pop().unwrap();
+                results.push(Term::Let {
+                    name: binder.name,
+                    value: Rc::new(value),
+                    body: Rc::new(body),
+                });
+            }
+        }
+    }
+    results.pop().unwrap()
+}
+
+fn spans(arena: &Arena, node: Node) -> Spans {
+    let Some(origin) = arena.origin(node) else {
+        return Vec::new();
+    };
+    enum Task {
+        Visit(Node, Span),
+        Leave(Span),
+    }
+    // subterms are visited in the same order as `to_term` builds them
+    let mut res = Vec::new();
+    let mut tasks = vec![Task::Visit(node, origin)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Visit(node, inherited) => {
+                let origin = arena.origin(node).unwrap_or(inherited);
+                tasks.push(Task::Leave(origin));
+                match *arena.get(node) {
+                    Core::BVar { index: _ } | Core::FVar { name: _ } => {}
+                    Core::App { func, arg } => {
+                        tasks.push(Task::Visit(arg, origin));
+                        tasks.push(Task::Visit(func, origin));
+                    }
+                    Core::Abs { hint: _, body } => tasks.push(Task::Visit(body, origin)),
+                    Core::Let {
+                        hint: _,
+                        value,
+                        body,
+                    } => {
+                        tasks.push(Task::Visit(body, origin));
+                        tasks.push(Task::Visit(value, origin));
+                    }
+                }
+            }
+            Task::Leave(origin) => res.push(origin),
+        }
+    }
+    res
+}
+
+// rebuilds a term bottom-up without recursion.
+// `replace` is given each subterm and the number of binders enclosing it in the term,
+// and returns the replacement of the subterm, or `None` to rebuild it from its rebuilt children.
+fn rebuild(
+    arena: &mut Arena,
+    node: Node,
+    mut replace: impl FnMut(&mut Arena, Node, usize) -> Option<Node>,
+) -> Node {
+    enum Task {
+        Visit(Node, usize),
+        Build(Node),
+    }
+    let mut results = Vec::new();
+    let mut tasks = vec![Task::Visit(node, 0)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Visit(node, depth) => {
+                if let Some(result) = replace(arena, node, depth) {
+                    results.push(result);
+                    continue;
+                }
+                tasks.push(Task::Build(node));
+                match *arena.get(node) {
+                    Core::BVar { index: _ } | Core::FVar { name: _ } => {}
+                    Core::App { func, arg } => {
+                        tasks.push(Task::Visit(arg, depth));
+                        tasks.push(Task::Visit(func, depth));
+                    }
+                    Core::Abs { hint: _, body } => {
+                        tasks.push(Task::Visit(body, depth + 1));
+                    }
+                    Core::Let {
+                        hint: _,
+                        value,
+                        body,
+                    } => {
+                        tasks.push(Task::Visit(body, depth + 1));
+                        tasks.push(Task::Visit(value, depth));
+                    }
+                }
+            }
+            Task::Build(node) => {
+                let core = match *arena.get(node) {
+                    Core::BVar { index: _ } | Core::FVar { name: _ } => {
+                        results.push(node);
+                        continue;
+                    }
+                    Core::App { func: _, arg: _ } => {
+                        let arg = results.pop().unwrap();
+                        let func = results.pop().unwrap();
+                        Core::App { func, arg }
+                    }
+                    Core::Abs { hint, body: _ } => {
+                        let body = results.pop().unwrap();
+                        Core::Abs { hint, body }
+                    }
+                    Core::Let {
+                        hint,
+                        value: _,
+                        body: _,
+                    } => {
+                        let body = results.pop().unwrap();
+                        let value = results.pop().unwrap();
+                        Core::Let { hint, value, body }
+                    }
+                };
+                // the rebuilt term comes from the same source as the original one
+                let origin = arena.origin(node);
+                results.push(arena.add_with_origin(core, origin));
+            }
+        }
+    }
+    results.pop().unwrap()
+}
+
+fn open(arena: &mut Arena, node: Node, term: Node) -> Node {
+    rebuild(arena, node, |arena, node, depth| {
+        // subterms without loose bound variables at this depth are shared as they are
+        if arena.loose_bvars(node) <= depth {
+            return Some(node);
+        }
+        match *arena.get(node) {
+            Core::BVar { index } => Some(if index == depth { term } else { node }),
+            _ => None,
+        }
+    })
+}
+
+fn close(arena: &mut Arena, node: Node, name: Symbol) -> Node {
+    rebuild(arena, node, |arena, node, depth| {
+        // subterms not containing the variable are shared as they are
+        if !arena.has_free_var(node, name) {
+            return Some(node);
+        }
+        match *arena.get(node) {
+            Core::FVar { name: _ } => Some(arena.bvar(depth)),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests_arena_add_term {
+    use super::*;
+
+    #[test]
+    fn test_add_term() {
+        // fun x -> let y = x z in fun x -> x y
+        let term = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::Let {
+                name: Symbol::from("y"),
+                value: Rc::new(Term::App {
+                    func: Rc::new(Term::Var {
+                        name: Symbol::from("x"),
+                    }),
+                    arg: Rc::new(Term::Var {
+                        name: Symbol::from("z"),
+                    }),
+                }),
+                body: Rc::new(Term::Abs {
+                    param: Symbol::from("x"),
+                    body: Rc::new(Term::App {
+                        func: Rc::new(Term::Var {
+                            name: Symbol::from("x"),
+                        }),
+                        arg: Rc::new(Term::Var {
+                            name: Symbol::from("y"),
+                        }),
+                    }),
+                }),
+            }),
+        };
+        let mut arena = Arena::new();
+        let node = arena.add_term(&term);
+        // fun x -> let y = #0 z in fun x -> #0 #1
+        let (b0, b1, z) = (arena.bvar(0), arena.bvar(1), arena.fvar("z"));
+        let value = arena.app(b0, z);
+        let body = arena.app(b0, b1);
+        let body = arena.abs("x", body);
+        let body = arena.let_("y", value, body);
+        let expected = arena.abs("x", body);
+        assert_eq!(node, expected);
+    }
+
+    #[test]
+    fn test_deep() {
+        // let x0 = y in let x1 = x0 in ... x99999
+        let mut term = Term::Var {
+            name: Symbol::from("x99999"),
+        };
+        for i in (0..100_000).rev() {
+            let value = match i {
+                0 => Symbol::from("y"),
+                i => Symbol::intern(&format!("x{}", i - 1)),
+            };
+            term = Term::Let {
+                name: Symbol::intern(&format!("x{i}")),
+                value: Rc::new(Term::Var { name: value }),
+                body: Rc::new(term),
+            };
+        }
+        let mut arena = Arena::new();
+        let node = arena.add_term(&term);
+        assert_eq!(arena.to_term(node), term);
+    }
+}
+
+#[cfg(test)]
+mod tests_arena_to_term {
+    use super::*;
+
+    #[test]
+    fn test_to_term() {
+        // fun x -> let x = x in fun y -> x y
+        let mut arena = Arena::new();
+        let (b0, b1, y) = (arena.bvar(0), arena.bvar(1), arena.fvar("y"));
+        let body = arena.app(b1, y);
+        let body = arena.abs("y", body);
+        let body = arena.let_("x", b0, body);
+        let node = arena.abs("x", body);
+        // fun x -> let x0 = x in fun y0 -> x0 y
+        let expected = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::Let {
+                name: Symbol::from("x0"),
+                value: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+                body: Rc::new(Term::Abs {
+                    param: Symbol::from("y0"),
+                    body: Rc::new(Term::App {
+                        func: Rc::new(Term::Var {
+                            name: Symbol::from("x0"),
+                        }),
+                        arg: Rc::new(Term::Var {
+                            name: Symbol::from("y"),
+                        }),
+                    }),
+                }),
+            }),
+        };
+        assert_eq!(arena.to_term(node), expected);
+    }
+
+    #[test]
+    fn test_fresh_hints() {
+        // fun x -> fun x -> x, where the outer hint is a fresh symbol displayed as x
+        let mut arena = Arena::new();
+        let b1 = arena.bvar(1);
+        let body = arena.abs("x", b1);
+        let node = arena.abs(Symbol::fresh(Symbol::from("x")), body);
+        // fun x -> fun x0 -> x
+        let expected = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::Abs {
+                param: Symbol::from("x0"),
+                body: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+            }),
+        };
+        assert_eq!(arena.to_term(node), expected);
+    }
+}
+
+#[cfg(test)]
+mod tests_arena_open_close {
+    use super::*;
+
+    #[test]
+    fn test_open_close() {
+        // fun y -> #1 #0
+        let mut arena = Arena::new();
+        let (b0, b1, x) = (arena.bvar(0), arena.bvar(1), arena.fvar("x"));
+        let body = arena.app(b1, b0);
+        let node = arena.abs("y", body);
+        // fun y -> x #0
+        let body = arena.app(x, b0);
+        let opened = arena.abs("y", body);
+        assert_eq!(arena.open(node, x), opened);
+        assert_eq!(arena.close(opened, Symbol::from("x")), node);
+    }
+
+    #[test]
+    fn test_deep() {
+        // fun y -> ... fun y -> #100000
+        let mut arena = Arena::new();
+        let x = arena.fvar("x");
+        let mut node = arena.bvar(100_000);
+        let mut opened = x;
+        for _ in 0..100_000 {
+            node = arena.abs("y", node);
+            opened = arena.abs("y", opened);
+        }
+        assert_eq!(arena.open(node, x), opened);
+        assert_eq!(arena.close(opened, Symbol::from("x")), node);
+    }
+}
+
+#[cfg(test)]
+mod tests_arena {
+    use super::*;
+
+    #[test]
+    fn test_memoized() {
+        // let y = x in fun z -> #2 y z
+        let mut arena = Arena::new();
+        let (b0, b1, b2, x) = (arena.bvar(0), arena.bvar(1), arena.bvar(2), arena.fvar("x"));
+        let func = arena.app(b2, b1);
+        let body = arena.app(func, b0);
+        let body = arena.abs("z", body);
+        let node = arena.let_("y", x, body);
+        assert_eq!(arena.free_vars(node), &HashSet::from([Symbol::from("x")]));
+        assert_eq!(arena.loose_bvars(node), 1);
+        assert!(arena.has_free_var(node, Symbol::from("x")));
+        assert!(!arena.has_free_var(node, Symbol::from("y")));
+        assert!(arena.contains_abs(node));
+        assert!(arena.contains_let(node));
+        assert!(!arena.contains_let(body));
+    }
+
+    #[test]
+    fn test_shared() {
+        // x x, built twice
+        let mut arena = Arena::new();
+        let x = arena.fvar("x");
+        let node = arena.app(x, x);
+        let x = arena.fvar("x");
+        assert_eq!(arena.app(x, x), node);
+        assert_eq!(arena.get(node), &Core::App { func: x, arg: x });
+        assert_eq!(arena.nodes.len(), 2);
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+
+use super::{
+    generator::{self, Backend, Names, Syntax},
+    lambda::Statement,
+    parser::Spans,
+    ski::Combinators,
+    source_map::Mapped,
+};
+
+// keywords of C++, and the names that the generated code uses
+const KEYWORDS: &str = "\
+    alignas alignof and and_eq asm auto bitand bitor bool break case catch char char8_t char16_t \
+    char32_t class compl concept const consteval constexpr constinit const_cast continue co_await \
+    co_return co_yield decltype default delete do double dynamic_cast else enum explicit export \
+    extern false float for friend goto if inline int long mutable namespace new noexcept not \
+    not_eq nullptr operator or or_eq private protected public register reinterpret_cast requires \
+    return short signed sizeof static static_assert static_cast struct switch template this \
+    thread_local throw true try typedef typeid typename union unsigned using virtual void \
+    volatile wchar_t while xor xor_eq";
+const HELPERS: &str = "App Eval X ret type";
+
+fn is_reserved(name: &str) -> bool {
+    static RESERVED: Lazy<HashSet<&str>> = Lazy::new(|| {
+        KEYWORDS
+            .split_whitespace()
+            .chain(HELPERS.split_whitespace())
+            .collect()
+    });
+    RESERVED.contains(name)
+}
+
+// applications are structs, which are instantiated only when their results are applied,
+// so that recursion through fixed points terminates
+const PREAMBLE: &str = "\
+template<class F, class X> struct App { template<class Y> using ret = typename F::template ret<X>::template ret<Y>; };
+template<class T> struct Eval { using type = T; };
+template<class F, class X> struct Eval<App<F, X>> { using type = typename Eval<typename Eval<F>::type::template ret<X>>::type; };
+";
+
+// the definitions of the combinators of `ski`, in which `{S}`, `{K}`, `{I}`, `{B}` and `{C}` stand for their names
+const COMBINATORS: &str = "\
+template<class x, class y> struct {S}$2 { template<class X> using ret = App<App<x, X>, App<y, X>>; };
+template<class x> struct {S}$1 { template<class X> using ret = {S}$2<x, X>; };
+struct {S} { template<class X> using ret = {S}$1<X>; };
+template<class x> struct {K}$1 { template<class X> using ret = x; };
+struct {K} { template<class X> using ret = {K}$1<X>; };
+struct {I} { template<class X> using ret = X; };
+template<class x, class y> struct {B}$2 { template<class X> using ret = App<x, App<y, X>>; };
+template<class x> struct {B}$1 { template<class X> using ret = {B}$2<x, X>; };
+struct {B} { template<class X> using ret = {B}$1<X>; };
+template<class x, class y> struct {C}$2 { template<class X> using ret = App<App<x, X>, y>; };
+template<class x> struct {C}$1 { template<class X> using ret = {C}$2<x, X>; };
+struct {C} { template<class X> using ret = {C}$1<X>; };
+";
+
+/// C++ template metaprograms, in which a function is a struct with a member template `ret`.
+///
+/// `App<F, X>` applies a function lazily, and `Eval<T>::type` evaluates applications until `T` is a function.
+/// Generated names may contain `$` and non-ASCII letters, which GCC, Clang and MSVC accept in identifiers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cpp;
+
+impl Syntax for Cpp {
+    fn application(&self, _is_function: bool) -> (&'static str, &'static str) {
+        ("App<", ", ")
+    }
+
+    fn constant(&self, name: &str, value: &str) -> String {
+        format!("struct {name} {{ template<class X> using ret = typename {value}::template ret<X>; }};\n")
+    }
+
+    // alias templates cannot be declared before they are defined, so the body comes first
+    fn function(&self, names: &[String], params: &[String], body: &str) -> Vec<(String, String)> {
+        let (name, fn_names) = names.split_last().expect("function without a body");
+        let template = |params: &[String]| {
+            let params: Vec<_> = params
+                .iter()
+                .map(|param| format!("class {param}"))
+                .collect();
+            format!("template<{}>", params.join(", "))
+        };
+        let mut res = vec![(
+            name.clone(),
+            format!("{} using {name} = {body};\n", template(params)),
+        )];
+        for (depth, (name, next_name)) in fn_names.iter().zip(&names[1..]).enumerate().rev() {
+            let args: Vec<_> = params[..depth]
+                .iter()
+                .map(String::as_str)
+                .chain(["X"])
+                .collect();
+            let next = format!("{next_name}<{}>", args.join(", "));
+            let template = if depth > 0 {
+                template(&params[..depth]) + " "
+            } else {
+                String::new()
+            };
+            let code =
+                format!("{template}struct {name} {{ template<class X> using ret = {next}; }};\n");
+            res.push((name.clone(), code));
+        }
+        res
+    }
+}
+
+impl Backend for Cpp {
+    fn generate_mapped(
+        &self,
+        program: &[Statement],
+        spans: &[Spans],
+        combinators: Option<&Combinators>,
+    ) -> (Mapped, Names) {
+        let mut names = Names::new(program, is_reserved);
+        let code = generator::generate_declarations(program, spans, &mut names, self, false);
+        // top-level names are declared first, as they may be referred to before their definitions,
+        // and free variables other than the combinators are left undefined
+        let mut res = Mapped::default();
+        res.push_str(PREAMBLE);
+        for (_, name) in &names.globals {
+            res.push_str(&format!("struct {name};\n"));
+        }
+        if let Some(combinators) = combinators {
+            res.push_str(&generator::define_combinators(
+                COMBINATORS,
+                combinators,
+                &names,
+            ));
+        }
+        res.push_marked(&code);
+        (res, names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::compiler::parser::parse;
+
+    #[test]
+    fn test_generate() {
+        let program = parse(indoc! {"
+            let K x y = x;
+            let Foo = K int (K X);
+            let Bar = let H x = x x in H H
+        "})
+        .unwrap();
+        let (code, names) = Cpp.generate(&program);
+        let expected = indoc! {"
+            struct K;
+            struct Foo;
+            struct Bar;
+            struct int_;
+            struct X_;
+            template<class x, class y> using K$2 = x;
+            template<class x> struct K$1 { template<class X> using ret = K$2<x, X>; };
+            struct K { template<class X> using ret = K$1<X>; };
+            struct Foo { template<class X> using ret = typename K$2<int_, App<K, X_>>::template ret<X>; };
+            template<class x> using Bar$H$1 = App<x, x>;
+            struct Bar$H { template<class X> using ret = Bar$H$1<X>; };
+            struct Bar { template<class X> using ret = typename App<Bar$H, Bar$H>::template ret<X>; };
+        "};
+        assert_eq!(code.strip_prefix(PREAMBLE).unwrap(), expected);
+        assert_eq!(names.globals[3], ("int".into(), String::from("int_")));
+    }
+}
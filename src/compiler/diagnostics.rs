@@ -0,0 +1,167 @@
+use std::{error, fmt};
+
+use nom::error::{Error, ErrorKind};
+
+/// A location in the source, with the line and the column counted from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// Returns the position of the byte `offset` in `source`.
+    pub fn new(source: &str, offset: usize) -> Position {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Position {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// An error found while compiling a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub position: Option<Position>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            message: message.into(),
+            position: None,
+        }
+    }
+
+    /// Converts an error of the parser, whose input is the rest of `source` where parsing failed.
+    pub fn from_parse_error(source: &str, err: &Error<&str>) -> Diagnostic {
+        let position = Some(Position::new(source, source.len() - err.input.len()));
+        // the lexer fails with `TakeUntil` at the beginning of a comment that is not closed
+        if err.code == ErrorKind::TakeUntil {
+            return Diagnostic {
+                message: String::from("unterminated comment"),
+                position,
+            };
+        }
+        // and the parser fails with `Verify` at the name of an unknown attribute
+        if err.code == ErrorKind::Verify {
+            let name = err
+                .input
+                .split([']', ' ', '\t', '\n'])
+                .next()
+                .unwrap_or_default();
+            return Diagnostic {
+                message: format!("unknown attribute `{name}`"),
+                position,
+            };
+        }
+        let near: String = err
+            .input
+            .split_whitespace()
+            .next()
+            .map_or(String::from("end of input"), |word| format!("`{word}`"));
+        Diagnostic {
+            message: format!("syntax error near {near}"),
+            position,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{position}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl error::Error for Diagnostic {}
+
+/// The errors that made a compilation fail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Diagnostics {
+        Diagnostics(vec![diagnostic])
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{diagnostic}")?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Diagnostics {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse;
+
+    #[test]
+    fn test_position() {
+        let source = "let foo = x;\nlet bar = λ;\n";
+        assert_eq!(
+            Position::new(source, 0),
+            Position {
+                offset: 0,
+                line: 1,
+                column: 1
+            }
+        );
+        assert_eq!(
+            Position::new(source, 25),
+            Position {
+                offset: 25,
+                line: 2,
+                column: 12
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_parse_error() {
+        let source = "let foo = x;\nlet bar = ;\n";
+        let err = parse(source).unwrap_err();
+        let diagnostic = Diagnostic::from_parse_error(source, &err);
+        assert_eq!(diagnostic.to_string(), "2:11: syntax error near `;`");
+
+        let source = "let foo =";
+        let err = parse(source).unwrap_err();
+        let diagnostic = Diagnostic::from_parse_error(source, &err);
+        assert_eq!(
+            diagnostic.to_string(),
+            "1:10: syntax error near end of input"
+        );
+
+        let source = "let foo = x;\n(* let bar = (* y *)\nlet baz = z;\n";
+        let err = parse(source).unwrap_err();
+        let diagnostic = Diagnostic::from_parse_error(source, &err);
+        assert_eq!(diagnostic.to_string(), "2:1: unterminated comment");
+
+        let source = "let foo = x;\nlet[@evl] bar = y;\n";
+        let err = parse(source).unwrap_err();
+        let diagnostic = Diagnostic::from_parse_error(source, &err);
+        assert_eq!(diagnostic.to_string(), "2:6: unknown attribute `evl`");
+    }
+}
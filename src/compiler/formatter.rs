@@ -0,0 +1,139 @@
+use nom::error::Error;
+
+use super::{
+    lexer,
+    parser::{self, Trivia},
+    printer,
+};
+
+// the comments in the source of a statement, which is tokenized again as statements keep no comments
+fn comments(source: &str) -> Vec<&str> {
+    let tokens = lexer::tokenize(source).expect("statements are tokenized by the parser");
+    tokens
+        .iter()
+        .flat_map(|token| &token.leading)
+        .filter_map(|trivia| match trivia {
+            Trivia::Comment(comment) => Some(*comment),
+            Trivia::BlankLine => None,
+        })
+        .collect()
+}
+
+fn push_trivia(res: &mut String, trivia: &[Trivia]) {
+    for trivia in trivia {
+        match trivia {
+            Trivia::Comment(comment) => {
+                res.push_str(comment);
+                res.push('\n');
+            }
+            // blank lines are collapsed, and dropped at the beginning of the file
+            Trivia::BlankLine => {
+                if !res.is_empty() && !res.ends_with("\n\n") {
+                    res.push('\n');
+                }
+            }
+        }
+    }
+}
+
+/// Reformats a program, keeping the comments and blank lines between statements.
+/// Statements that contain comments are kept as written, since the comments have no place in terms.
+pub fn format(input: &str, width: usize) -> Result<String, Error<&str>> {
+    let (items, trailing) = parser::parse_items(input)?;
+    let mut res = String::new();
+    for item in &items {
+        push_trivia(&mut res, &item.leading);
+        let comments = comments(item.source);
+        if let Some(last) = comments.last() {
+            res.push_str(item.source);
+            // a line comment at the end would comment out the `;`
+            if last.starts_with("--") && item.source.ends_with(last) {
+                res.push('\n');
+            }
+            res.push(';');
+        } else {
+            res.push_str(&printer::print_statement(&item.stmt, width));
+        }
+        if let Some(comment) = item.trailing {
+            res.push(' ');
+            res.push_str(comment);
+        }
+        res.push('\n');
+    }
+    push_trivia(&mut res, &trailing);
+    while res.ends_with("\n\n") {
+        res.pop();
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::compiler::printer::DEFAULT_WIDTH;
+
+    #[test]
+    fn test_format() {
+        let input = indoc! {"
+
+
+            (* combinators *)
+            let Id = fun x -> x;   (* identity *)
+            let Const x y =
+              x;
+
+
+
+            (* numbers *)
+            let Two = fun f x -> f (
+              (* applied twice *)
+              f x);
+            let[@eval]   Three = Two (fun f x -> f x);
+
+        "};
+        let expected = indoc! {"
+            (* combinators *)
+            let Id x = x; (* identity *)
+            let Const x y = x;
+
+            (* numbers *)
+            let Two = fun f x -> f (
+              (* applied twice *)
+              f x);
+            let[@eval] Three = Two (fun f x -> f x);
+        "};
+        assert_eq!(format(input, DEFAULT_WIDTH).unwrap(), expected);
+        assert_eq!(format(expected, DEFAULT_WIDTH).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_nested_comments() {
+        let input = indoc! {"
+            (* let Old = (* unused *) x; *)
+            let   Id x = x; -- identity
+            -- numbers
+            let Two = fun f x ->
+              f (f x) -- twice
+            ;
+            let Three = fun f x -> f (Two f x)   (* last *)
+        "};
+        let expected = indoc! {"
+            (* let Old = (* unused *) x; *)
+            let Id x = x; -- identity
+            -- numbers
+            let Two = fun f x ->
+              f (f x) -- twice
+            ;
+            let Three f x = f (Two f x); (* last *)
+        "};
+        assert_eq!(format(input, DEFAULT_WIDTH).unwrap(), expected);
+        assert_eq!(format(expected, DEFAULT_WIDTH).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_error() {
+        assert!(format("let foo = ;", DEFAULT_WIDTH).is_err());
+    }
+}
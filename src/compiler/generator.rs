@@ -1,193 +1,847 @@
-use std::{rc::Rc, vec::Vec};
+use std::collections::{HashMap, HashSet};
 
-use super::lambda::{Statement, Term};
+use once_cell::sync::Lazy;
+use unicode_ident::{is_xid_continue, is_xid_start};
 
-fn generate_term(term: &Term) -> String {
-    match term {
-        Term::Var { name } => name.clone(),
-        Term::App { func, arg } => {
-            let func = generate_term(func);
-            let arg = generate_term(arg);
-            format!("App<{func}, {arg}>")
+use super::{
+    lambda::{Statement, Term, Visitor},
+    parser::{Span, Spans},
+    pipeline::Invariant,
+    ski::Combinators,
+    source_map::{self, Mapped},
+    symbol::Symbol,
+};
+
+/// Invariants that terms must satisfy to be generated: abstractions and lets may only appear
+/// as chains at the top of declarations and in values of lets.
+pub const REQUIRES: &[Invariant] = &[Invariant::BinderFreeApps, Invariant::LetFreeAbsBodies];
+
+// keywords of TypeScript and names of global types in its standard library, none of which may name generated types
+const KEYWORDS: &str = "\
+    abstract accessor any as asserts async await bigint boolean break case catch class const \
+    constructor continue debugger declare default delete do else enum export extends false \
+    finally for from function get global if implements import in infer instanceof interface is \
+    keyof let module namespace never new null number object of out override package private \
+    protected public readonly require return satisfies set static string super switch symbol \
+    this throw true try type typeof undefined unique unknown var void while with yield";
+const GLOBAL_TYPES: &str = "\
+    Array ArrayLike Awaited BigInt Boolean CallableFunction Capitalize ConstructorParameters \
+    Date Error Exclude Extract Function Generator InstanceType Intl Iterable IterableIterator \
+    Iterator JSON Lowercase Map Math NewableFunction NoInfer NonNullable Number Object Omit \
+    OmitThisParameter Parameters Partial Pick Promise PromiseLike PropertyKey Readonly \
+    ReadonlyArray Record RegExp Required ReturnType Set String Symbol TemplateStringsArray \
+    ThisParameterType ThisType Uncapitalize Uppercase WeakMap WeakSet";
+
+fn is_reserved(name: &str) -> bool {
+    // the helpers of every runtime are reserved, so that names do not depend on the runtime
+    static RESERVED: Lazy<HashSet<&str>> = Lazy::new(|| {
+        let helpers = ["Fun", "App", "Fn", "Call"];
+        let words = KEYWORDS
+            .split_whitespace()
+            .chain(GLOBAL_TYPES.split_whitespace());
+        words.chain(helpers).collect()
+    });
+    RESERVED.contains(name)
+}
+
+// replaces characters that cannot appear in TypeScript identifiers, and `$` unless it is allowed.
+// Unicode letters are kept, while primes are replaced, e.g. `x'` becomes `x_`
+fn sanitize(name: &str, allow_dollar: bool) -> String {
+    let mut res: String = name
+        .chars()
+        .map(|c| {
+            if is_xid_continue(c) || (allow_dollar && c == '$') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !res.starts_with(|c: char| is_xid_start(c) || c == '_' || c == '$') {
+        res.insert(0, '_');
+    }
+    res
+}
+
+// the names to try for a name in order: itself, then with `_` and numbers appended
+fn candidates(base: String) -> impl Iterator<Item = String> {
+    let escaped = format!("{base}_");
+    let numbered = (1..).map({
+        let base = base.clone();
+        move |n: usize| format!("{base}_{n}")
+    });
+    [base, escaped].into_iter().chain(numbered)
+}
+
+/// The definitions of functions and applications that the generated code relies on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Runtime {
+    /// `Fun` and `App`, which are defined at the top of the generated code.
+    #[default]
+    Local,
+    /// `Fn` and `Call` imported from [hotscript](https://github.com/gvergnaud/hotscript),
+    /// so that the generated functions can be passed to functions of hotscript such as `Tuples.Map`.
+    Hotscript,
+}
+
+impl Runtime {
+    fn preamble(self) -> &'static str {
+        match self {
+            Runtime::Local => {
+                "interface Fun { arg: unknown; ret: unknown }\n\
+                 type App<F, X> = F extends Fun ? (F & { arg: X })[\"ret\"] : never;\n"
+            }
+            Runtime::Hotscript => "import { Call, Fn } from \"hotscript\";\n",
         }
-        Term::Abs { param: _, body: _ } => panic!("undefined"),
-        Term::Let {
-            name: _,
-            value: _,
-            body: _,
-        } => panic!("undefined"),
-    }
-}
-
-fn generate_statement(stmt: &Statement) -> String {
-    match stmt {
-        Statement::Declaration { name, value } => match value.as_ref() {
-            Term::Var { name: _ } => {
-                let ret = generate_term(value);
-                format!("type {name} = {ret};\n")
-            }
-            Term::App { func: _, arg: _ } => {
-                let ret = generate_term(value);
-                format!("type {name} = {ret};\n")
-            }
-            Term::Abs { param: _, body: _ } => {
-                let mut res = String::new();
-                let mut current_term = value.as_ref().clone();
-                let mut if_args = String::new();
-                let mut depth = 0;
-
-                while let Term::Abs { param, body } = current_term {
-                    let if_sig = if depth == 0 {
-                        name.clone()
-                    } else {
-                        format!("{name}${depth}<{if_args}>")
-                    };
-
-                    let next_depth = depth + 1;
-
-                    let if_ret = if depth == 0 {
-                        format!("{name}${next_depth}<this[\"arg\"]>")
-                    } else {
-                        format!("{name}${next_depth}<{if_args}, this[\"arg\"]>")
-                    };
-
-                    let if_code = format!("interface {if_sig} extends Fun {{ ret: {if_ret} }}\n");
-                    res.push_str(if_code.as_str());
-
-                    current_term = body.as_ref().clone();
-                    if depth == 0 {
-                        if_args.push_str(param.as_str());
-                    } else {
-                        if_args.push_str(format!(", {param}").as_str());
-                    }
-                    depth = next_depth;
+    }
+
+    // the definitions of the combinators of `ski`, in which `{S}`, `{K}`, `{I}`, `{B}` and `{C}` stand for their names
+    fn combinators(self) -> &'static str {
+        match self {
+            Runtime::Local => {
+                "interface {S} extends Fun { ret: {S}$1<this[\"arg\"]> }\n\
+                 interface {S}$1<x> extends Fun { ret: {S}$2<x, this[\"arg\"]> }\n\
+                 interface {S}$2<x, y> extends Fun { ret: App<App<x, this[\"arg\"]>, App<y, this[\"arg\"]>> }\n\
+                 interface {K} extends Fun { ret: {K}$1<this[\"arg\"]> }\n\
+                 interface {K}$1<x> extends Fun { ret: x }\n\
+                 interface {I} extends Fun { ret: this[\"arg\"] }\n\
+                 interface {B} extends Fun { ret: {B}$1<this[\"arg\"]> }\n\
+                 interface {B}$1<x> extends Fun { ret: {B}$2<x, this[\"arg\"]> }\n\
+                 interface {B}$2<x, y> extends Fun { ret: App<x, App<y, this[\"arg\"]>> }\n\
+                 interface {C} extends Fun { ret: {C}$1<this[\"arg\"]> }\n\
+                 interface {C}$1<x> extends Fun { ret: {C}$2<x, this[\"arg\"]> }\n\
+                 interface {C}$2<x, y> extends Fun { ret: App<App<x, this[\"arg\"]>, y> }\n"
+            }
+            Runtime::Hotscript => {
+                "interface {S} extends Fn { return: {S}$1<this[\"arg0\"]> }\n\
+                 interface {S}$1<x> extends Fn { return: {S}$2<x, this[\"arg0\"]> }\n\
+                 interface {S}$2<x, y> extends Fn { return: Call<Extract<Call<Extract<x, Fn>, this[\"arg0\"]>, Fn>, Call<Extract<y, Fn>, this[\"arg0\"]>> }\n\
+                 interface {K} extends Fn { return: {K}$1<this[\"arg0\"]> }\n\
+                 interface {K}$1<x> extends Fn { return: x }\n\
+                 interface {I} extends Fn { return: this[\"arg0\"] }\n\
+                 interface {B} extends Fn { return: {B}$1<this[\"arg0\"]> }\n\
+                 interface {B}$1<x> extends Fn { return: {B}$2<x, this[\"arg0\"]> }\n\
+                 interface {B}$2<x, y> extends Fn { return: Call<Extract<x, Fn>, Call<Extract<y, Fn>, this[\"arg0\"]>> }\n\
+                 interface {C} extends Fn { return: {C}$1<this[\"arg0\"]> }\n\
+                 interface {C}$1<x> extends Fn { return: {C}$2<x, this[\"arg0\"]> }\n\
+                 interface {C}$2<x, y> extends Fn { return: Call<Extract<Call<Extract<x, Fn>, this[\"arg0\"]>, Fn>, y> }\n"
+            }
+        }
+    }
+}
+
+// fills in the definitions of the combinators with the names given to them where they are free in the program.
+// the others keep their names, which are distinct from those of the program
+pub(super) fn define_combinators(
+    definitions: &str,
+    combinators: &Combinators,
+    names: &Names,
+) -> String {
+    let placeholders = ["{S}", "{K}", "{I}", "{B}", "{C}"];
+    let mut res = definitions.to_string();
+    for (placeholder, name) in placeholders.into_iter().zip(combinators.names()) {
+        let new_name = names
+            .global_names
+            .get(name.as_str())
+            .map_or(name.as_str(), String::as_str);
+        res = res.replace(placeholder, new_name);
+    }
+    res
+}
+
+// how the types made from declarations are written, which differs between backends
+pub(super) trait Syntax {
+    // the texts that surround the function and the argument of an application, depending on whether
+    // the function is known to be a function type
+    fn application(&self, is_function: bool) -> (&'static str, &'static str);
+
+    // a type whose value is the term
+    fn constant(&self, name: &str, value: &str) -> String;
+
+    // a function whose types after taking each parameter are named in order, the last one being the body,
+    // which are returned with their names
+    fn function(&self, names: &[String], params: &[String], body: &str) -> Vec<(String, String)>;
+}
+
+// the name of a type where it is declared, which is not qualified by its namespace
+fn unqualified(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(_, name)| name)
+}
+
+impl Syntax for Runtime {
+    // `Call` of hotscript only accepts functions, while `App` returns `never` for other types
+    fn application(&self, is_function: bool) -> (&'static str, &'static str) {
+        match self {
+            Runtime::Local => ("App<", ", "),
+            Runtime::Hotscript if is_function => ("Call<", ", "),
+            Runtime::Hotscript => ("Call<Extract<", ", Fn>, "),
+        }
+    }
+
+    fn constant(&self, name: &str, value: &str) -> String {
+        format!("type {} = {value};\n", unqualified(name))
+    }
+
+    fn function(&self, names: &[String], params: &[String], body: &str) -> Vec<(String, String)> {
+        let (interface, ret, arg) = match self {
+            Runtime::Local => ("Fun", "ret", "this[\"arg\"]"),
+            Runtime::Hotscript => ("Fn", "return", "this[\"arg0\"]"),
+        };
+        let mut res = Vec::new();
+        for (depth, (name, next_name)) in names.iter().zip(&names[1..]).enumerate() {
+            let args = params[..depth].join(", ");
+            let (sig, next) = if depth == 0 {
+                (unqualified(name).to_string(), format!("{next_name}<{arg}>"))
+            } else {
+                (
+                    format!("{}<{args}>", unqualified(name)),
+                    format!("{next_name}<{args}, {arg}>"),
+                )
+            };
+            let code = format!("interface {sig} extends {interface} {{ {ret}: {next} }}\n");
+            res.push((name.clone(), code));
+        }
+        let name = names.last().expect("function without a body");
+        let code = format!(
+            "type {}<{}> = {body};\n",
+            unqualified(name),
+            params.join(", ")
+        );
+        res.push((name.clone(), code));
+        res
+    }
+}
+
+/// A target language of the generator, which writes a transformed program as code.
+pub trait Backend {
+    /// Generates a program whose declarations have the spans given by [`Pipeline::run_with_spans`],
+    /// and returns the names given to its top-level names.
+    /// The types and applications in the code are mapped to the spans of the terms they come from.
+    /// The combinators that [`ski::translate`] made the program refer to, if any, are defined before it.
+    ///
+    /// [`Pipeline::run_with_spans`]: super::pipeline::Pipeline::run_with_spans
+    /// [`ski::translate`]: super::ski::translate
+    fn generate_mapped(
+        &self,
+        program: &[Statement],
+        spans: &[Spans],
+        combinators: Option<&Combinators>,
+    ) -> (Mapped, Names);
+
+    /// Generates a program, and returns the names given to its top-level names.
+    fn generate(&self, program: &[Statement]) -> (String, Names) {
+        let (mapped, names) = self.generate_mapped(program, &[], None);
+        (mapped.code, names)
+    }
+}
+
+/// TypeScript types, which are functions and applications of the runtime.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TypeScript {
+    pub runtime: Runtime,
+    /// Places the helper types of each declaration `Foo` in `namespace Foo`, e.g. `Foo.$1`,
+    /// so that only the declared names are visible at the top level.
+    pub namespaces: bool,
+}
+
+impl Backend for TypeScript {
+    fn generate_mapped(
+        &self,
+        program: &[Statement],
+        spans: &[Spans],
+        combinators: Option<&Combinators>,
+    ) -> (Mapped, Names) {
+        let mut names = Names::new(program, is_reserved);
+        let mut res = Mapped::default();
+        res.push_str(self.runtime.preamble());
+        if let Some(combinators) = combinators {
+            let definitions = self.runtime.combinators();
+            res.push_str(&define_combinators(definitions, combinators, &names));
+        }
+        res.push_marked(&generate_declarations(
+            program,
+            spans,
+            &mut names,
+            &self.runtime,
+            self.namespaces,
+        ));
+        (res, names)
+    }
+}
+
+/// The names of the generated types, which are distinct from each other and from the reserved names of TypeScript.
+///
+/// Source names are kept as long as they are valid, and are escaped by appending `_` or a number otherwise.
+/// The names of helper types, which are the interfaces of abstractions and the types of hoisted lets,
+/// are made from the name of their declaration and `$`, and never collide with source names.
+/// With namespaces, they are qualified by the namespace of their top-level declaration instead, e.g. `Foo.$1`.
+#[derive(Debug)]
+pub struct Names {
+    /// The names given to top-level names, which are the declared names followed by the free variables,
+    /// in the order they appear.
+    pub globals: Vec<(Symbol, String)>,
+    // the generated names and the source names they stand for, which are `None` for helpers
+    owners: HashMap<String, Option<&'static str>>,
+    global_names: HashMap<&'static str, String>,
+    // the arities of top-level functions generated so far, and the types that take all their arguments
+    known: HashMap<String, (usize, String)>,
+    // the types that are interfaces of functions
+    functions: HashSet<String>,
+    // the names that the target language reserves
+    is_reserved: fn(&str) -> bool,
+    // the namespace of the helpers of the declaration being generated
+    namespace: Option<String>,
+}
+
+impl Names {
+    pub(super) fn new(program: &[Statement], is_reserved: fn(&str) -> bool) -> Names {
+        #[derive(Default)]
+        struct FreeVars {
+            scopes: Vec<Symbol>,
+            vars: Vec<Symbol>,
+        }
+        impl Visitor<'_> for FreeVars {
+            fn var(&mut self, name: Symbol) {
+                if !self.scopes.contains(&name) {
+                    self.vars.push(name);
                 }
+            }
+            fn bind(&mut self, name: Symbol) {
+                self.scopes.push(name);
+            }
+            fn unbind(&mut self, _name: Symbol) {
+                self.scopes.pop();
+            }
+        }
+        let mut globals = Vec::new();
+        let mut free_vars = FreeVars::default();
+        for stmt in program {
+            let Statement::Declaration {
+                name,
+                value,
+                attrs: _,
+                doc: _,
+            } = stmt;
+            globals.push(*name);
+            value.walk(&mut free_vars);
+        }
+        globals.extend(free_vars.vars);
+
+        let mut names = Names {
+            globals: Vec::new(),
+            owners: HashMap::new(),
+            global_names: HashMap::new(),
+            known: HashMap::new(),
+            functions: HashSet::new(),
+            is_reserved,
+            namespace: None,
+        };
+        let mut seen = HashSet::new();
+        let globals: Vec<_> = globals
+            .into_iter()
+            .filter(|name| seen.insert(name.as_str()))
+            .collect();
+        // names that are valid as they are take precedence over escaped ones
+        let mut escaped = Vec::new();
+        for name in globals {
+            let source = name.as_str();
+            if !is_reserved(source) && sanitize(source, true) == source {
+                names.owners.insert(source.to_string(), Some(source));
+                names.global_names.insert(source, source.to_string());
+            } else {
+                escaped.push(name);
+            }
+            names.globals.push((name, String::new()));
+        }
+        for name in escaped {
+            let source = name.as_str();
+            let new_name = candidates(sanitize(source, true))
+                .find(|name| !(names.is_reserved)(name) && !names.owners.contains_key(name))
+                .unwrap();
+            names.owners.insert(new_name.clone(), Some(source));
+            names.global_names.insert(source, new_name);
+        }
+        for (name, new_name) in &mut names.globals {
+            *new_name = names.global_names[name.as_str()].clone();
+        }
+        for stmt in program {
+            let Statement::Declaration {
+                name,
+                value,
+                attrs: _,
+                doc: _,
+            } = stmt;
+            if let Term::Abs { param: _, body: _ } = value.as_ref() {
+                let new_name = names.global_names[name.as_str()].clone();
+                names.functions.insert(new_name);
+            }
+        }
+        names
+    }
+
+    // the start of the names of the helpers of a type, which is qualified by the namespace if it is the type
+    // that names the namespace
+    fn helper_prefix(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) if namespace == name => format!("{name}."),
+            _ => name.to_string(),
+        }
+    }
+
+    // a name for a helper type, which contains `$` so as not to be taken by local names
+    fn helper(&mut self, base: String) -> String {
+        let new_name = candidates(base)
+            .find(|name| !self.owners.contains_key(name))
+            .unwrap();
+        self.owners.insert(new_name.clone(), None);
+        new_name
+    }
+
+    // a name for a hoisted let, which is referred to by its symbol from the rest of the declaration
+    fn hoisted(&mut self, base: String) -> (String, Symbol) {
+        let new_name = self.helper(base);
+        let symbol = Symbol::intern(&new_name);
+        self.global_names.insert(symbol.as_str(), new_name.clone());
+        (new_name, symbol)
+    }
 
-                let type_name = format!("{name}${depth}");
-                let type_ret = generate_term(&current_term);
-                let type_code = format!("type {type_name}<{if_args}> = {type_ret};\n");
-                res.push_str(type_code.as_str());
+    // a name for a parameter, which may shadow a top-level name of the same source name,
+    // but not the namespace that qualifies the helpers referred to in its scope
+    fn local(&self, source: &'static str, locals: &HashMap<&'static str, String>) -> String {
+        candidates(sanitize(source, false))
+            .find(|name| {
+                !(self.is_reserved)(name)
+                    && self.namespace.as_ref() != Some(name)
+                    && self
+                        .owners
+                        .get(name)
+                        .is_none_or(|owner| *owner == Some(source))
+                    && locals.values().all(|local| local != name)
+            })
+            .unwrap()
+    }
+}
 
-                res
+// the spans of the subterms of a term by their addresses, which are empty if the term has no spans
+fn subterm_spans(term: &Term, spans: &[Span]) -> HashMap<*const Term, Span> {
+    struct Subterms<'a>(Vec<&'a Term>);
+    impl<'a> Visitor<'a> for Subterms<'a> {
+        fn leave(&mut self, term: &'a Term) {
+            self.0.push(term);
+        }
+    }
+    if spans.is_empty() {
+        return HashMap::new();
+    }
+    let mut subterms = Subterms(Vec::new());
+    term.walk(&mut subterms);
+    subterms
+        .0
+        .into_iter()
+        .map(|term| term as *const Term)
+        .zip(spans.iter().copied())
+        .collect()
+}
+
+fn generate_term(
+    term: &Term,
+    spans: &[Span],
+    names: &Names,
+    locals: &HashMap<&'static str, String>,
+    syntax: &dyn Syntax,
+) -> String {
+    enum Task<'a> {
+        Term(&'a Term),
+        Text(&'a str),
+        // the start of the code generated from a term
+        Mark(&'a Term),
+    }
+    let spans = subterm_spans(term, spans);
+    let mark = |term: &Term| {
+        spans
+            .get(&(term as *const Term))
+            .map_or(String::new(), |&span| source_map::mark(span))
+    };
+    // terms are written out in order from an explicit stack, so that deeply nested terms do not overflow
+    let mut res = String::new();
+    let mut tasks = vec![Task::Term(term)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Text(text) => res.push_str(text),
+            Task::Mark(term) => res.push_str(&mark(term)),
+            Task::Term(Term::Var { name }) => {
+                let name = name.as_str();
+                let new_name = locals
+                    .get(name)
+                    .or_else(|| names.global_names.get(name))
+                    .expect("unknown name");
+                res.push_str(new_name);
             }
-            Term::Let {
+            Task::Term(term @ Term::App { func: _, arg: _ }) => {
+                // the whole spine is written at once, calling the function directly if it is known and saturated
+                let mut args = Vec::new();
+                let mut apps = Vec::new();
+                let mut head = term;
+                while let Term::App { func, arg } = head {
+                    args.push(arg.as_ref());
+                    apps.push(head);
+                    head = func;
+                }
+                args.reverse();
+                apps.reverse();
+                let global = match head {
+                    Term::Var { name } if !locals.contains_key(name.as_str()) => {
+                        names.global_names.get(name.as_str())
+                    }
+                    _ => None,
+                };
+                let known = global
+                    .and_then(|new_name| names.known.get(new_name))
+                    .filter(|(arity, _)| *arity <= args.len());
+                let mut parts = Vec::new();
+                let (rest, is_function) = match known {
+                    Some((arity, function)) => {
+                        parts.push(Task::Mark(apps[arity - 1]));
+                        parts.push(Task::Text(function));
+                        parts.push(Task::Text("<"));
+                        for (i, arg) in args[..*arity].iter().enumerate() {
+                            if i > 0 {
+                                parts.push(Task::Text(", "));
+                            }
+                            parts.push(Task::Term(arg));
+                        }
+                        parts.push(Task::Text(">"));
+                        (&args[*arity..], false)
+                    }
+                    None => {
+                        parts.push(Task::Term(head));
+                        let is_function = global.is_some_and(|name| names.functions.contains(name));
+                        (&args[..], is_function)
+                    }
+                };
+                // the innermost application is written last, as its function may be known to be an interface
+                let rest_apps = &apps[apps.len() - rest.len()..];
+                for i in (0..rest.len()).rev() {
+                    res.push_str(&mark(rest_apps[i]));
+                    res.push_str(syntax.application(is_function && i == 0).0);
+                }
+                for (i, arg) in rest.iter().enumerate() {
+                    parts.push(Task::Text(syntax.application(is_function && i == 0).1));
+                    parts.push(Task::Term(arg));
+                    parts.push(Task::Text(">"));
+                }
+                tasks.extend(parts.into_iter().rev());
+            }
+            Task::Term(Term::Abs { param: _, body: _ }) => panic!("undefined"),
+            Task::Term(Term::Let {
                 name: _,
                 value: _,
                 body: _,
-            } => {
-                let mut res = String::new();
-                let mut current_term = value.as_ref().clone();
-
-                while let Term::Let {
-                    name: inner_name,
-                    value: inner_value,
-                    body: inner_body,
-                } = current_term
-                {
-                    let new_name = String::from(format!("{name}${inner_name}"));
-                    let hyp_stmt = Statement::Declaration {
-                        name: new_name.clone(),
-                        value: Rc::clone(&inner_value),
-                    };
-                    let code = &generate_statement(&hyp_stmt);
-                    res.push_str(code);
-
-                    current_term = inner_body.subst(
-                        &inner_name,
-                        &Term::Var {
-                            name: new_name.clone(),
-                        },
-                    );
-                }
+            }) => panic!("undefined"),
+        }
+    }
+    res
+}
+
+// marks code as coming from the term whose subterms have the spans
+fn mark_code(spans: &[Span], code: String) -> String {
+    match spans.last() {
+        Some(&span) => source_map::mark(span) + &code,
+        None => code,
+    }
+}
+
+// generates a declaration whose name is already given, and whose value has the spans
+fn generate_declaration(
+    name: &str,
+    value: &Term,
+    spans: &[Span],
+    names: &mut Names,
+    syntax: &dyn Syntax,
+) -> Vec<(String, String)> {
+    match value {
+        Term::Var { name: _ } => {
+            let ret = generate_term(value, spans, names, &HashMap::new(), syntax);
+            let code = mark_code(spans, syntax.constant(name, &ret));
+            vec![(name.to_string(), code)]
+        }
+        Term::App { func: _, arg: _ } => {
+            let ret = generate_term(value, spans, names, &HashMap::new(), syntax);
+            let code = mark_code(spans, syntax.constant(name, &ret));
+            vec![(name.to_string(), code)]
+        }
+        Term::Abs { param: _, body: _ } => {
+            let mut current_term = value;
+            let mut current_spans = spans;
+            let mut fn_names = vec![name.to_string()];
+            // the spans of the terms after taking each parameter, which the types named by `fn_names` come from
+            let mut fn_spans = vec![current_spans];
+            let mut params = Vec::new();
+            let mut locals = HashMap::new();
+            let prefix = names.helper_prefix(name);
+
+            while let Term::Abs { param, body } = current_term {
+                let next_name = names.helper(format!("{prefix}${}", fn_names.len()));
+                fn_names.push(next_name);
+                let param_name = names.local(param.as_str(), &locals);
+                params.push(param_name.clone());
+                locals.insert(param.as_str(), param_name);
+                current_term = body;
+                current_spans = &current_spans[..current_spans.len().saturating_sub(1)];
+                fn_spans.push(current_spans);
+            }
+
+            let body = generate_term(current_term, current_spans, names, &locals, syntax);
+            let res = syntax
+                .function(&fn_names, &params, &body)
+                .into_iter()
+                .map(|(type_name, code)| {
+                    let i = fn_names.iter().position(|name| *name == type_name);
+                    let code = mark_code(i.map_or(&[], |i| fn_spans[i]), code);
+                    (type_name, code)
+                })
+                .collect();
+
+            // functions are known only after their declarations, so that direct calls never form cycles,
+            // which TypeScript may reject where applications through `App` are deferred
+            if let Some(Some(_)) = names.owners.get(name) {
+                let if_name = fn_names.pop().expect("function without a body");
+                names
+                    .known
+                    .insert(name.to_string(), (params.len(), if_name));
+            }
+            names.functions.insert(name.to_string());
 
-                let hyp_stmt = Statement::Declaration {
-                    name: name.clone(),
-                    value: Rc::new(current_term.clone()),
+            res
+        }
+        Term::Let {
+            name: _,
+            value: _,
+            body: _,
+        } => {
+            let mut res = Vec::new();
+            let mut current_term = value;
+            let mut current_spans = spans;
+            let prefix = names.helper_prefix(name);
+            // hoisted names are substituted all at once instead of once per binding
+            let mut renaming = HashMap::new();
+
+            while let Term::Let {
+                name: inner_name,
+                value: inner_value,
+                body: inner_body,
+            } = current_term
+            {
+                let inner = sanitize(inner_name.as_str(), false);
+                let (new_name, new_symbol) = names.hoisted(format!("{prefix}${inner}"));
+                // the spans of the value come first, followed by those of the body and the let itself
+                let (value_spans, body_spans) = match current_spans.split_last() {
+                    Some((_, spans)) => spans.split_at(inner_value.size()),
+                    None => (&[][..], &[][..]),
                 };
-                let code = &generate_statement(&hyp_stmt);
-                res.push_str(code);
+                let types = generate_declaration(
+                    &new_name,
+                    &inner_value.rename(&renaming),
+                    value_spans,
+                    names,
+                    syntax,
+                );
+                res.extend(types);
 
-                res
+                renaming.insert(*inner_name, new_symbol);
+                current_term = inner_body;
+                current_spans = body_spans;
             }
-        },
+
+            let types = generate_declaration(
+                name,
+                &current_term.rename(&renaming),
+                current_spans,
+                names,
+                syntax,
+            );
+            res.extend(types);
+
+            res
+        }
     }
 }
 
-pub fn generate(program: &Vec<Statement>) -> String {
+/// Generates the declarations of a program, without the definitions of the runtime they refer to,
+/// and returns the names given to them.
+pub fn generate_statements_with_names(program: &[Statement], runtime: Runtime) -> (String, Names) {
+    let mut names = Names::new(program, is_reserved);
+    // without spans, the code is not marked
+    let res = generate_declarations(program, &[], &mut names, &runtime, false);
+    (res, names)
+}
+
+// a TSDoc comment of a text, in which `*/` is escaped so as not to end the comment, as are the characters
+// of source map marks. helper notes are a single line, so that they can be indented in namespaces
+fn tsdoc(text: &str) -> String {
+    let text = source_map::escape_marks(&text.replace("*/", "*\\/"));
+    if !text.contains('\n') {
+        return format!("/** {text} */\n");
+    }
+    let mut res = String::from("/**\n");
+    for line in text.lines() {
+        if line.is_empty() {
+            res.push_str(" *\n");
+        } else {
+            res.push_str(&format!(" * {line}\n"));
+        }
+    }
+    res.push_str(" */\n");
+    res
+}
+
+// generates the declarations of a program, with the names given by the backend.
+// the code is marked with the spans of the terms, which are missing for declarations without spans
+pub(super) fn generate_declarations(
+    program: &[Statement],
+    spans: &[Spans],
+    names: &mut Names,
+    syntax: &dyn Syntax,
+    namespaces: bool,
+) -> String {
     let mut res = String::new();
-    res.push_str("interface Fun { arg: unknown; ret: unknown }\n");
-    res.push_str("type App<F, X> = F extends Fun ? (F & { arg: X })[\"ret\"] : never;\n");
-    for stmt in program.iter() {
-        res.push_str(generate_statement(stmt).as_str());
+    for (i, stmt) in program.iter().enumerate() {
+        let Statement::Declaration {
+            name,
+            value,
+            attrs: _,
+            doc,
+        } = stmt;
+        let spans = spans.get(i).map_or(&[][..], Vec::as_slice);
+        let new_name = names.global_names[name.as_str()].clone();
+        if namespaces {
+            names.namespace = Some(new_name.clone());
+        }
+        let types = generate_declaration(&new_name, value, spans, names, syntax);
+        // helpers are the types declared in the namespace, whose names are qualified,
+        // and are exported so as to be referred to from outside it
+        let mut helpers = String::new();
+        for (type_name, code) in types {
+            // the doc comment is shown on the declared type, and its helpers point back at it
+            let comment = match doc {
+                Some(doc) if type_name == new_name => tsdoc(doc),
+                Some(_) => tsdoc(&format!("Helper of {{@link {new_name}}}.")),
+                None => String::new(),
+            };
+            if type_name.contains('.') {
+                if !comment.is_empty() {
+                    helpers.push_str("  ");
+                    helpers.push_str(&comment);
+                }
+                helpers.push_str("  export ");
+                helpers.push_str(&code);
+            } else {
+                res.push_str(&comment);
+                res.push_str(&code);
+            }
+        }
+        if !helpers.is_empty() {
+            res.push_str(&format!("namespace {new_name} {{\n{helpers}}}\n"));
+        }
     }
+    names.namespace = None;
     res
 }
 
+/// Generates the declarations of a program, without the definitions of `Fun` and `App` they refer to.
+pub fn generate_statements(program: &[Statement]) -> String {
+    generate_statements_with_names(program, Runtime::Local).0
+}
+
+/// Generates a program for the runtime, and returns the names given to its top-level names.
+pub fn generate_with_names(program: &[Statement], runtime: Runtime) -> (String, Names) {
+    let backend = TypeScript {
+        runtime,
+        namespaces: false,
+    };
+    backend.generate(program)
+}
+
+pub fn generate(program: &[Statement]) -> String {
+    generate_with_names(program, Runtime::Local).0
+}
+
 #[cfg(test)]
 mod tests {
+    use std::rc::Rc;
+
     use indoc::indoc;
 
     use super::*;
+    use crate::compiler::parser::parse;
 
     #[test]
     fn test_generate() {
         let program = vec![
             Statement::Declaration {
-                name: String::from("foo"),
+                name: Symbol::from("foo"),
                 value: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
+                attrs: vec![],
+                doc: None,
             },
             Statement::Declaration {
-                name: String::from("bar"),
+                name: Symbol::from("bar"),
                 value: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("x"),
+                        name: Symbol::from("x"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                 }),
+                attrs: vec![],
+                doc: None,
             },
             Statement::Declaration {
-                name: String::from("baz"),
+                name: Symbol::from("baz"),
                 value: Rc::new(Term::Abs {
-                    param: String::from("x"),
+                    param: Symbol::from("x"),
                     body: Rc::new(Term::Abs {
-                        param: String::from("y"),
+                        param: Symbol::from("y"),
                         body: Rc::new(Term::App {
                             func: Rc::new(Term::Var {
-                                name: String::from("x"),
+                                name: Symbol::from("x"),
                             }),
                             arg: Rc::new(Term::Var {
-                                name: String::from("y"),
+                                name: Symbol::from("y"),
                             }),
                         }),
                     }),
                 }),
+                attrs: vec![],
+                doc: None,
             },
             Statement::Declaration {
-                name: String::from("qux"),
+                name: Symbol::from("qux"),
                 value: Rc::new(Term::Let {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                     value: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     body: Rc::new(Term::Let {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                         value: Rc::new(Term::Var {
-                            name: String::from("x"),
+                            name: Symbol::from("x"),
                         }),
                         body: Rc::new(Term::App {
                             func: Rc::new(Term::Var {
-                                name: String::from("x"),
+                                name: Symbol::from("x"),
                             }),
                             arg: Rc::new(Term::Var {
-                                name: String::from("z"),
+                                name: Symbol::from("z"),
                             }),
                         }),
                     }),
                 }),
+                attrs: vec![],
+                doc: None,
             },
         ];
         let expected = indoc! {"
@@ -204,4 +858,249 @@ mod tests {
         "};
         assert_eq!(generate(&program), expected);
     }
+
+    #[test]
+    fn test_names() {
+        let program = parse(indoc! {"
+            let string x = x;
+            let string_ = string;
+            let Record = fun App -> App string_ string Fun;
+            let F this = fun x -> this x;
+            let x = F
+        "})
+        .unwrap();
+        let expected = indoc! {r#"
+            interface string_1 extends Fun { ret: string_1$1<this["arg"]> }
+            type string_1$1<x> = x;
+            type string_ = string_1;
+            interface Record_ extends Fun { ret: Record_$1<this["arg"]> }
+            type Record_$1<App_> = App<App<App<App_, string_>, string_1>, Fun_>;
+            interface F extends Fun { ret: F$1<this["arg"]> }
+            interface F$1<this_> extends Fun { ret: F$2<this_, this["arg"]> }
+            type F$2<this_, x> = App<this_, x>;
+            type x = F;
+        "#};
+        let (code, names) = generate_statements_with_names(&program, Runtime::Local);
+        assert_eq!(code, expected);
+        let globals: Vec<_> = names
+            .globals
+            .iter()
+            .map(|(name, new_name)| (name.as_str(), new_name.as_str()))
+            .collect();
+        assert_eq!(
+            globals,
+            [
+                ("string", "string_1"),
+                ("string_", "string_"),
+                ("Record", "Record_"),
+                ("F", "F"),
+                ("x", "x"),
+                ("Fun", "Fun_"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unicode_names() {
+        let program = parse(indoc! {"
+            let Δ = λx. x x;
+            let K' = \\x y. x;
+            let K_ = \\x' x. K' x' x
+        "})
+        .unwrap();
+        let expected = indoc! {r#"
+            interface Δ extends Fun { ret: Δ$1<this["arg"]> }
+            type Δ$1<x> = App<x, x>;
+            interface K__ extends Fun { ret: K__$1<this["arg"]> }
+            interface K__$1<x> extends Fun { ret: K__$2<x, this["arg"]> }
+            type K__$2<x, y> = x;
+            interface K_ extends Fun { ret: K_$1<this["arg"]> }
+            interface K_$1<x_> extends Fun { ret: K_$2<x_, this["arg"]> }
+            type K_$2<x_, x> = K__$2<x_, x>;
+        "#};
+        let (code, _) = generate_statements_with_names(&program, Runtime::Local);
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn test_helper_names() {
+        // names with `$` or characters not allowed in TypeScript can be read from JSON
+        let mut program = parse("let baz x = x; let qux = let x = y in x").unwrap();
+        for name in ["baz$1", "qux$x", "a b"] {
+            program.push(Statement::Declaration {
+                name: Symbol::from(name),
+                value: Rc::new(Term::Var {
+                    name: Symbol::from("baz"),
+                }),
+                attrs: vec![],
+                doc: None,
+            });
+        }
+        let expected = indoc! {r#"
+            interface baz extends Fun { ret: baz$1_<this["arg"]> }
+            type baz$1_<x> = x;
+            type qux$x_ = y;
+            type qux = qux$x_;
+            type baz$1 = baz;
+            type qux$x = baz;
+            type a_b = baz;
+        "#};
+        assert_eq!(generate_statements(&program), expected);
+    }
+
+    #[test]
+    fn test_known_calls() {
+        let program = parse(indoc! {"
+            let Early = K a b;
+            let K x y = x;
+            let Saturated = K a b;
+            let Unsaturated = K a;
+            let Over = K a b c (K d e);
+            let Shadowed K = K a b;
+            let Self x = Self x
+        "})
+        .unwrap();
+        let expected = indoc! {r#"
+            type Early = App<App<K, a>, b>;
+            interface K extends Fun { ret: K$1<this["arg"]> }
+            interface K$1<x> extends Fun { ret: K$2<x, this["arg"]> }
+            type K$2<x, y> = x;
+            type Saturated = K$2<a, b>;
+            type Unsaturated = App<K, a>;
+            type Over = App<App<K$2<a, b>, c>, K$2<d, e>>;
+            interface Shadowed extends Fun { ret: Shadowed$1<this["arg"]> }
+            type Shadowed$1<K> = App<App<K, a>, b>;
+            interface Self extends Fun { ret: Self$1<this["arg"]> }
+            type Self$1<x> = App<Self, x>;
+        "#};
+        assert_eq!(generate_statements(&program), expected);
+    }
+
+    #[test]
+    fn test_hotscript() {
+        let program = parse(indoc! {"
+            let Early = K a b;
+            let K x y = x;
+            let Saturated = K a b c;
+            let Param f = f a b;
+            let Local = let H x = x x in H H
+        "})
+        .unwrap();
+        let expected = indoc! {r#"
+            import { Call, Fn } from "hotscript";
+            type Early = Call<Extract<Call<K, a>, Fn>, b>;
+            interface K extends Fn { return: K$1<this["arg0"]> }
+            interface K$1<x> extends Fn { return: K$2<x, this["arg0"]> }
+            type K$2<x, y> = x;
+            type Saturated = Call<Extract<K$2<a, b>, Fn>, c>;
+            interface Param extends Fn { return: Param$1<this["arg0"]> }
+            type Param$1<f> = Call<Extract<Call<Extract<f, Fn>, a>, Fn>, b>;
+            interface Local$H extends Fn { return: Local$H$1<this["arg0"]> }
+            type Local$H$1<x> = Call<Extract<x, Fn>, x>;
+            type Local = Call<Local$H, Local$H>;
+        "#};
+        assert_eq!(
+            generate_with_names(&program, Runtime::Hotscript).0,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let program = parse(indoc! {"
+            let K x y = x;
+            let Foo = let h Foo = K Foo in h;
+            let Bar = K Foo
+        "})
+        .unwrap();
+        let expected = indoc! {r#"
+            interface K extends Fun { ret: K.$1<this["arg"]> }
+            namespace K {
+              export interface $1<x> extends Fun { ret: K.$2<x, this["arg"]> }
+              export type $2<x, y> = x;
+            }
+            type Foo = Foo.$h;
+            namespace Foo {
+              export interface $h extends Fun { ret: Foo.$h$1<this["arg"]> }
+              export type $h$1<Foo_> = App<K, Foo_>;
+            }
+            type Bar = App<K, Foo>;
+        "#};
+        let backend = TypeScript {
+            runtime: Runtime::Local,
+            namespaces: true,
+        };
+        let (code, names) = backend.generate(&program);
+        assert_eq!(code.strip_prefix(Runtime::Local.preamble()), Some(expected));
+        assert_eq!(names.globals[1], ("Foo".into(), String::from("Foo")));
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let program = parse(indoc! {"
+            (** The constant function.
+
+                Ignores `y` and returns `x`. *)
+            let K x y = x;
+            (** Never ends with */ in a comment. *)
+            let Foo = let h Foo = K Foo in h
+        "})
+        .unwrap();
+        let expected = indoc! {r#"
+            /**
+             * The constant function.
+             *
+             * Ignores `y` and returns `x`.
+             */
+            interface K extends Fun { ret: K$1<this["arg"]> }
+            /** Helper of {@link K}. */
+            interface K$1<x> extends Fun { ret: K$2<x, this["arg"]> }
+            /** Helper of {@link K}. */
+            type K$2<x, y> = x;
+            /** Helper of {@link Foo}. */
+            interface Foo$h extends Fun { ret: Foo$h$1<this["arg"]> }
+            /** Helper of {@link Foo}. */
+            type Foo$h$1<Foo> = App<K, Foo>;
+            /** Never ends with *\/ in a comment. */
+            type Foo = Foo$h;
+        "#};
+        let (code, _) = TypeScript::default().generate(&program);
+        assert_eq!(code.strip_prefix(Runtime::Local.preamble()), Some(expected));
+
+        // the characters that mark the code for source maps are escaped
+        let program = parse("(** a \u{E000}0:1\u{E001} b *)\nlet Id = x").unwrap();
+        let (code, _) = TypeScript::default().generate(&program);
+        assert_eq!(
+            code.strip_prefix(Runtime::Local.preamble()),
+            Some("/** a \\uE0000:1\\uE001 b */\ntype Id = x;\n")
+        );
+    }
+
+    #[test]
+    fn test_deep() {
+        // let foo = f (f ... (f x))
+        let mut term = Term::Var {
+            name: Symbol::from("x"),
+        };
+        for _ in 0..100_000 {
+            term = Term::App {
+                func: Rc::new(Term::Var {
+                    name: Symbol::from("f"),
+                }),
+                arg: Rc::new(term),
+            };
+        }
+        let program = vec![Statement::Declaration {
+            name: Symbol::from("foo"),
+            value: Rc::new(term),
+            attrs: vec![],
+            doc: None,
+        }];
+        let expected = format!(
+            "type foo = {}x{};\n",
+            "App<f, ".repeat(100_000),
+            ">".repeat(100_000)
+        );
+        assert!(generate(&program).ends_with(&expected));
+    }
 }
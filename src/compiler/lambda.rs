@@ -1,95 +1,718 @@
-use std::{collections::HashSet, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
-#[derive(Clone, Debug, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+use super::symbol::Symbol;
+
+// serde follows the structure of terms recursively, so subterms are (de)serialized on a new stack when it runs out
+mod grow {
+    use std::rc::Rc;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Term;
+
+    const RED_ZONE: usize = 64 * 1024;
+    const STACK_SIZE: usize = 2 * 1024 * 1024;
+
+    pub fn serialize<S: Serializer>(term: &Rc<Term>, serializer: S) -> Result<S::Ok, S::Error> {
+        stacker::maybe_grow(RED_ZONE, STACK_SIZE, || term.as_ref().serialize(serializer))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rc<Term>, D::Error> {
+        stacker::maybe_grow(RED_ZONE, STACK_SIZE, || {
+            Term::deserialize(deserializer).map(Rc::new)
+        })
+    }
+}
+
+// Terms may be nested arbitrarily deep, so every operation on them, including dropping and comparison,
+// runs on an explicit stack instead of recursing.
+// Only serde recurses into subterms, and it is given a new stack when the current one runs out (see `grow`).
+// Traversals are written as a `Visitor` or a `Folder`, so that only `walk` and `fold` follow the structure of terms.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Term {
     Var {
-        name: String,
+        name: Symbol,
     },
     App {
+        #[serde(with = "grow")]
         func: Rc<Term>,
+        #[serde(with = "grow")]
         arg: Rc<Term>,
     },
     Abs {
-        param: String,
+        param: Symbol,
+        #[serde(with = "grow")]
         body: Rc<Term>,
     },
     Let {
-        name: String,
+        name: Symbol,
+        #[serde(with = "grow")]
         value: Rc<Term>,
+        #[serde(with = "grow")]
         body: Rc<Term>,
     },
 }
 
+/// Receives the parts of a term in order as it is walked by [`Term::walk`].
+pub trait Visitor<'a> {
+    /// Called on each subterm before its children.
+    fn enter(&mut self, _term: &'a Term) {}
+    /// Called on each subterm after its children.
+    fn leave(&mut self, _term: &'a Term) {}
+    /// Called on each occurrence of a variable, either free or bound.
+    fn var(&mut self, _name: Symbol) {}
+    /// Called when the scope of a binder begins, that is, before the body of `fun` or `let`.
+    fn bind(&mut self, _name: Symbol) {}
+    /// Called when the scope of a binder ends.
+    fn unbind(&mut self, _name: Symbol) {}
+}
+
+/// Replaces free variables of a term as it is rebuilt by [`Term::fold`].
+/// Bound variables are never passed to the folder.
+pub trait Folder {
+    /// Returns the term replacing a free variable, or `None` to keep it.
+    fn free_var(&mut self, name: Symbol) -> Option<Rc<Term>>;
+    /// Returns whether a binder would capture variables of the replacing terms.
+    /// Such a binder is renamed to a fresh symbol if any variable is replaced in its scope.
+    fn captures(&self, _name: Symbol) -> bool {
+        false
+    }
+}
+
 impl Term {
-    // pub fn print(&self) -> String {
-    //     match self {
-    //         Term::Var { name } => name.clone(),
-    //         Term::App { func, arg } => format!("({} {})", func.print(), arg.print()),
-    //         Term::Abs { param, body } => format!("(λ{}. {})", param, body.print()),
-    //         Term::Let { name, value, body } => {
-    //             format!("(let {} = {} in {})", name, value.print(), body.print())
-    //         }
-    //     }
-    // }
-
-    pub fn free_vars(&self) -> HashSet<String> {
-        match self {
-            Term::Var { name } => HashSet::from([name.clone()]),
-            Term::App { func, arg } => {
-                let func_fvs = func.free_vars();
-                let arg_fvs = arg.free_vars();
-                func_fvs.union(&arg_fvs).cloned().collect()
+    pub fn size(&self) -> usize {
+        struct Size(usize);
+        impl Visitor<'_> for Size {
+            fn enter(&mut self, _term: &Term) {
+                self.0 += 1;
             }
-            Term::Abs { param, body } => {
-                let mut body_fvs = body.free_vars();
-                body_fvs.remove(param);
-                body_fvs
+        }
+        let mut size = Size(0);
+        self.walk(&mut size);
+        size.0
+    }
+
+    pub fn free_vars(&self) -> HashSet<Symbol> {
+        #[derive(Default)]
+        struct FreeVars {
+            fvs: HashSet<Symbol>,
+            // the same name may be bound more than once on a path, so binders are counted
+            bound: HashMap<Symbol, usize>,
+        }
+        impl Visitor<'_> for FreeVars {
+            fn var(&mut self, name: Symbol) {
+                if !self.bound.contains_key(&name) {
+                    self.fvs.insert(name);
+                }
             }
-            Term::Let { name, value, body } => {
-                let value_fvs = value.free_vars();
-                let mut body_fvs = body.free_vars();
-                body_fvs.remove(name);
-                value_fvs.union(&body_fvs).cloned().collect()
+            fn bind(&mut self, name: Symbol) {
+                *self.bound.entry(name).or_default() += 1;
+            }
+            fn unbind(&mut self, name: Symbol) {
+                let count = self.bound.get_mut(&name).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    self.bound.remove(&name);
+                }
+            }
+        }
+        let mut free_vars = FreeVars::default();
+        self.walk(&mut free_vars);
+        free_vars.fvs
+    }
+
+    pub fn subst(&self, name: Symbol, term: &Term) -> Term {
+        struct Subst {
+            name: Symbol,
+            term: Rc<Term>,
+            fvs: HashSet<Symbol>,
+        }
+        impl Folder for Subst {
+            fn free_var(&mut self, name: Symbol) -> Option<Rc<Term>> {
+                (name == self.name).then(|| Rc::clone(&self.term))
+            }
+            fn captures(&self, name: Symbol) -> bool {
+                self.fvs.contains(&name)
             }
         }
+        self.fold(&mut Subst {
+            name,
+            term: Rc::new(term.clone()),
+            fvs: term.free_vars(),
+        })
     }
 
-    pub fn subst(&self, name: &String, term: &Term) -> Term {
-        match self {
-            Term::Var { name: var_name } => {
-                if var_name == name {
-                    term.clone()
-                } else {
-                    self.clone()
+    /// Renames free variables simultaneously.
+    /// The new names are assumed not to be captured by the binders in the term.
+    pub fn rename(&self, renaming: &HashMap<Symbol, Symbol>) -> Term {
+        struct Rename<'a>(&'a HashMap<Symbol, Symbol>);
+        impl Folder for Rename<'_> {
+            fn free_var(&mut self, name: Symbol) -> Option<Rc<Term>> {
+                let &name = self.0.get(&name)?;
+                Some(Rc::new(Term::Var { name }))
+            }
+        }
+        if renaming.is_empty() {
+            return self.clone();
+        }
+        self.fold(&mut Rename(renaming))
+    }
+
+    /// Walks the term in order, passing its parts to the visitor.
+    pub fn walk<'a>(&'a self, visitor: &mut impl Visitor<'a>) {
+        enum Task<'a> {
+            Enter(&'a Term),
+            Leave(&'a Term),
+            Bind(Symbol),
+            Unbind(Symbol),
+        }
+        let mut tasks = vec![Task::Enter(self)];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Enter(term) => {
+                    visitor.enter(term);
+                    tasks.push(Task::Leave(term));
+                    match term {
+                        Term::Var { name } => visitor.var(*name),
+                        Term::App { func, arg } => {
+                            tasks.push(Task::Enter(arg));
+                            tasks.push(Task::Enter(func));
+                        }
+                        Term::Abs { param, body } => {
+                            tasks.push(Task::Unbind(*param));
+                            tasks.push(Task::Enter(body));
+                            tasks.push(Task::Bind(*param));
+                        }
+                        Term::Let { name, value, body } => {
+                            tasks.push(Task::Unbind(*name));
+                            tasks.push(Task::Enter(body));
+                            tasks.push(Task::Bind(*name));
+                            tasks.push(Task::Enter(value));
+                        }
+                    }
                 }
+                Task::Leave(term) => visitor.leave(term),
+                Task::Bind(name) => visitor.bind(name),
+                Task::Unbind(name) => visitor.unbind(name),
             }
-            Term::App { func, arg } => Term::App {
-                func: Rc::new(func.subst(name, term)),
-                arg: Rc::new(arg.subst(name, term)),
-            },
-            Term::Abs { param, body } => Term::Abs {
-                param: param.clone(),
-                body: if param == name {
-                    Rc::clone(body)
-                } else {
-                    Rc::new(body.subst(name, term))
-                },
-            },
-            Term::Let {
-                name: let_name,
-                value,
-                body,
-            } => Term::Let {
-                name: let_name.clone(),
-                value: Rc::new(value.subst(name, term)),
-                body: if let_name == name {
-                    Rc::clone(body)
-                } else {
-                    Rc::new(body.subst(name, term))
+        }
+    }
+
+    /// Rebuilds the term with its free variables replaced by the folder.
+    /// Subterms without replaced variables are shared with the original term.
+    pub fn fold(&self, folder: &mut impl Folder) -> Term {
+        let (replacements, renamed) = self.plan_fold(folder);
+        let mut replacements = replacements.into_iter();
+        let mut renamed = renamed.into_iter();
+
+        enum Task<'a> {
+            Visit(&'a Term),
+            Bind(&'a Term),
+            Unbind(Symbol),
+            // a term with the new name of its binder if renamed
+            Build(&'a Term, Option<Symbol>),
+        }
+        // rebuilt subterms, which are None if they are the same as the original ones
+        fn child(rebuilt: Option<Rc<Term>>, term: &Rc<Term>) -> Rc<Term> {
+            rebuilt.unwrap_or_else(|| Rc::clone(term))
+        }
+
+        // new names of the enclosing binders, where the name is None if not renamed
+        let mut scope: HashMap<Symbol, Vec<Option<Symbol>>> = HashMap::new();
+        let mut results: Vec<Option<Rc<Term>>> = Vec::new();
+        let mut tasks = vec![Task::Visit(self)];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(Term::Var { name }) => {
+                    let rebuilt = match scope.get(name).and_then(|names| names.last()) {
+                        Some(new_name) => new_name.map(|name| Rc::new(Term::Var { name })),
+                        None => replacements.next().unwrap(),
+                    };
+                    results.push(rebuilt);
+                }
+                Task::Visit(term @ Term::App { func, arg }) => {
+                    tasks.push(Task::Build(term, None));
+                    tasks.push(Task::Visit(arg));
+                    tasks.push(Task::Visit(func));
+                }
+                Task::Visit(term @ Term::Abs { param: _, body: _ }) => {
+                    tasks.push(Task::Bind(term));
+                }
+                Task::Visit(
+                    term @ Term::Let {
+                        name: _,
+                        value,
+                        body: _,
+                    },
+                ) => {
+                    tasks.push(Task::Bind(term));
+                    tasks.push(Task::Visit(value));
+                }
+                Task::Bind(term) => {
+                    let (name, body) = match term {
+                        Term::Abs { param, body } => (*param, body),
+                        Term::Let {
+                            name,
+                            value: _,
+                            body,
+                        } => (*name, body),
+                        _ => unreachable!(),
+                    };
+                    let new_name = renamed.next().unwrap().then(|| Symbol::fresh(name));
+                    scope.entry(name).or_default().push(new_name);
+                    tasks.push(Task::Build(term, new_name));
+                    tasks.push(Task::Unbind(name));
+                    tasks.push(Task::Visit(body));
+                }
+                Task::Unbind(name) => {
+                    let names = scope.get_mut(&name).unwrap();
+                    names.pop();
+                    if names.is_empty() {
+                        scope.remove(&name);
+                    }
+                }
+                Task::Build(term, new_name) => {
+                    let rebuilt = match term {
+                        Term::Var { name: _ } => unreachable!(),
+                        Term::App { func, arg } => {
+                            let new_arg = results.pop().unwrap();
+                            let new_func = results.pop().unwrap();
+                            (new_func.is_some() || new_arg.is_some()).then(|| {
+                                Rc::new(Term::App {
+                                    func: child(new_func, func),
+                                    arg: child(new_arg, arg),
+                                })
+                            })
+                        }
+                        Term::Abs { param, body: _ } => {
+                            // a binder is renamed only if a variable is replaced in its body
+                            let new_body = results.pop().unwrap();
+                            new_body.map(|body| {
+                                Rc::new(Term::Abs {
+                                    param: new_name.unwrap_or(*param),
+                                    body,
+                                })
+                            })
+                        }
+                        Term::Let { name, value, body } => {
+                            let new_body = results.pop().unwrap();
+                            let new_value = results.pop().unwrap();
+                            (new_value.is_some() || new_body.is_some()).then(|| {
+                                Rc::new(Term::Let {
+                                    name: new_name.unwrap_or(*name),
+                                    value: child(new_value, value),
+                                    body: child(new_body, body),
+                                })
+                            })
+                        }
+                    };
+                    results.push(rebuilt);
+                }
+            }
+        }
+        match results.pop().unwrap() {
+            Some(term) => Rc::unwrap_or_clone(term),
+            None => self.clone(),
+        }
+    }
+
+    /// Decides how [`Term::fold`] rebuilds the term, by walking it once.
+    /// Returns the replacements of the free occurrences of variables, in order,
+    /// and whether each binder is renamed, in the order of the binders.
+    fn plan_fold(&self, folder: &mut impl Folder) -> (Vec<Option<Rc<Term>>>, Vec<bool>) {
+        struct Plan<'f, F> {
+            folder: &'f mut F,
+            replacements: Vec<Option<Rc<Term>>>,
+            renamed: Vec<bool>,
+            // the same name may be bound more than once on a path, so binders are counted
+            bound: HashMap<Symbol, usize>,
+            // the indices of the enclosing binders, and of those which capture replacing terms
+            enclosing: Vec<usize>,
+            capturing: Vec<usize>,
+        }
+        impl<F: Folder> Visitor<'_> for Plan<'_, F> {
+            fn var(&mut self, name: Symbol) {
+                if self.bound.contains_key(&name) {
+                    return;
+                }
+                let replacement = self.folder.free_var(name);
+                if replacement.is_some() {
+                    // the enclosing binders of a renamed binder are already renamed
+                    for &binder in self.capturing.iter().rev() {
+                        if self.renamed[binder] {
+                            break;
+                        }
+                        self.renamed[binder] = true;
+                    }
+                }
+                self.replacements.push(replacement);
+            }
+            fn bind(&mut self, name: Symbol) {
+                *self.bound.entry(name).or_default() += 1;
+                let binder = self.renamed.len();
+                self.renamed.push(false);
+                self.enclosing.push(binder);
+                if self.folder.captures(name) {
+                    self.capturing.push(binder);
+                }
+            }
+            fn unbind(&mut self, name: Symbol) {
+                let count = self.bound.get_mut(&name).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    self.bound.remove(&name);
+                }
+                let binder = self.enclosing.pop();
+                if self.capturing.last() == binder.as_ref() {
+                    self.capturing.pop();
+                }
+            }
+        }
+        let mut plan = Plan {
+            folder,
+            replacements: Vec::new(),
+            renamed: Vec::new(),
+            bound: HashMap::new(),
+            enclosing: Vec::new(),
+            capturing: Vec::new(),
+        };
+        self.walk(&mut plan);
+        (plan.replacements, plan.renamed)
+    }
+
+    /// Performs a single step of normal-order (leftmost-outermost) reduction.
+    /// `let` is reduced like a beta-redex.
+    /// Returns `None` if the term is already in normal form.
+    pub fn reduce(&self) -> Option<Term> {
+        enum Dir {
+            Func,
+            Arg,
+            Body,
+        }
+        // visited subterms with the index of their parent, searched in normal order
+        let mut visited: Vec<(&Term, Option<(usize, Dir)>)> = Vec::new();
+        let mut stack = vec![(self, None)];
+        while let Some((term, parent)) = stack.pop() {
+            let index = visited.len();
+            visited.push((term, parent));
+            let contracted = match term {
+                Term::Var { name: _ } => None,
+                Term::App { func, arg } => match func.as_ref() {
+                    Term::Abs { param, body } => Some(body.subst(*param, arg)),
+                    _ => {
+                        stack.push((arg, Some((index, Dir::Arg))));
+                        stack.push((func, Some((index, Dir::Func))));
+                        None
+                    }
                 },
-            },
+                Term::Abs { param: _, body } => {
+                    stack.push((body, Some((index, Dir::Body))));
+                    None
+                }
+                Term::Let { name, value, body } => Some(body.subst(*name, value)),
+            };
+            if let Some(mut result) = contracted {
+                // rebuild the ancestors of the redex
+                let mut parent = &visited[index].1;
+                while let Some((index, dir)) = parent {
+                    let (ancestor, grandparent) = &visited[*index];
+                    result = match (ancestor, dir) {
+                        (Term::App { func: _, arg }, Dir::Func) => Term::App {
+                            func: Rc::new(result),
+                            arg: Rc::clone(arg),
+                        },
+                        (Term::App { func, arg: _ }, Dir::Arg) => Term::App {
+                            func: Rc::clone(func),
+                            arg: Rc::new(result),
+                        },
+                        (Term::Abs { param, body: _ }, Dir::Body) => Term::Abs {
+                            param: *param,
+                            body: Rc::new(result),
+                        },
+                        _ => unreachable!(),
+                    };
+                    parent = grandparent;
+                }
+                return Some(result);
+            }
+        }
+        None
+    }
+
+    /// Reduces the term to its normal form.
+    /// Returns `None` if no normal form is reached within `max_steps` reduction steps,
+    /// or if an intermediate term grows larger than `max_size` nodes.
+    pub fn normalize(&self, max_steps: usize, max_size: usize) -> Option<Term> {
+        let mut term = self.clone();
+        let mut steps = 0;
+        while let Some(next) = term.reduce() {
+            if steps == max_steps || next.size() > max_size {
+                return None;
+            }
+            term = next;
+            steps += 1;
+        }
+        Some(term)
+    }
+}
+
+impl PartialEq for Term {
+    fn eq(&self, other: &Term) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if std::ptr::eq(a, b) {
+                continue;
+            }
+            match (a, b) {
+                (Term::Var { name: a_name }, Term::Var { name: b_name }) => {
+                    if a_name != b_name {
+                        return false;
+                    }
+                }
+                (
+                    Term::App {
+                        func: a_func,
+                        arg: a_arg,
+                    },
+                    Term::App {
+                        func: b_func,
+                        arg: b_arg,
+                    },
+                ) => {
+                    stack.push((a_arg, b_arg));
+                    stack.push((a_func, b_func));
+                }
+                (
+                    Term::Abs {
+                        param: a_param,
+                        body: a_body,
+                    },
+                    Term::Abs {
+                        param: b_param,
+                        body: b_body,
+                    },
+                ) => {
+                    if a_param != b_param {
+                        return false;
+                    }
+                    stack.push((a_body, b_body));
+                }
+                (
+                    Term::Let {
+                        name: a_name,
+                        value: a_value,
+                        body: a_body,
+                    },
+                    Term::Let {
+                        name: b_name,
+                        value: b_value,
+                        body: b_body,
+                    },
+                ) => {
+                    if a_name != b_name {
+                        return false;
+                    }
+                    stack.push((a_body, b_body));
+                    stack.push((a_value, b_value));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Drop for Term {
+    fn drop(&mut self) {
+        // children owned only by this term are detached and dropped one by one,
+        // replacing them by a shared placeholder so that dropping their parents does not recurse
+        let mut placeholder = None;
+        let mut detached = Vec::new();
+        detach_children(self, &mut placeholder, &mut detached);
+        while let Some(term) = detached.pop() {
+            if let Ok(mut term) = Rc::try_unwrap(term) {
+                detach_children(&mut term, &mut placeholder, &mut detached);
+            }
+        }
+    }
+}
+
+fn detach_children(
+    term: &mut Term,
+    placeholder: &mut Option<Rc<Term>>,
+    detached: &mut Vec<Rc<Term>>,
+) {
+    let mut detach = |child: &mut Rc<Term>| {
+        if Rc::strong_count(child) == 1 && !matches!(child.as_ref(), Term::Var { name: _ }) {
+            let placeholder = placeholder.get_or_insert_with(|| {
+                Rc::new(Term::Var {
+                    name: Symbol::EMPTY,
+                })
+            });
+            detached.push(std::mem::replace(child, Rc::clone(placeholder)));
+        }
+    };
+    match term {
+        Term::Var { name: _ } => {}
+        Term::App { func, arg } => {
+            detach(func);
+            detach(arg);
+        }
+        Term::Abs { param: _, body } => detach(body),
+        Term::Let {
+            name: _,
+            value,
+            body,
+        } => {
+            detach(value);
+            detach(body);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_term_walk {
+    use super::*;
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Visitor<'_> for Events {
+        fn enter(&mut self, term: &Term) {
+            let kind = match term {
+                Term::Var { name: _ } => "var",
+                Term::App { func: _, arg: _ } => "app",
+                Term::Abs { param: _, body: _ } => "abs",
+                Term::Let {
+                    name: _,
+                    value: _,
+                    body: _,
+                } => "let",
+            };
+            self.0.push(format!("enter {kind}"));
+        }
+        fn leave(&mut self, _term: &Term) {
+            self.0.push(String::from("leave"));
+        }
+        fn var(&mut self, name: Symbol) {
+            self.0.push(format!("var {name}"));
+        }
+        fn bind(&mut self, name: Symbol) {
+            self.0.push(format!("bind {name}"));
+        }
+        fn unbind(&mut self, name: Symbol) {
+            self.0.push(format!("unbind {name}"));
         }
     }
+
+    #[test]
+    fn test_walk() {
+        // let x = y in fun z -> x z
+        let term = Term::Let {
+            name: Symbol::from("x"),
+            value: Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }),
+            body: Rc::new(Term::Abs {
+                param: Symbol::from("z"),
+                body: Rc::new(Term::App {
+                    func: Rc::new(Term::Var {
+                        name: Symbol::from("x"),
+                    }),
+                    arg: Rc::new(Term::Var {
+                        name: Symbol::from("z"),
+                    }),
+                }),
+            }),
+        };
+        let mut events = Events::default();
+        term.walk(&mut events);
+        let expected = [
+            "enter let",
+            "enter var",
+            "var y",
+            "leave",
+            "bind x",
+            "enter abs",
+            "bind z",
+            "enter app",
+            "enter var",
+            "var x",
+            "leave",
+            "enter var",
+            "var z",
+            "leave",
+            "leave",
+            "unbind z",
+            "leave",
+            "unbind x",
+            "leave",
+        ];
+        assert_eq!(events.0, expected);
+    }
+}
+
+#[cfg(test)]
+mod tests_term_fold {
+    use super::*;
+
+    // replaces every free variable by `y`, renaming binders named `y`
+    struct ToY;
+
+    impl Folder for ToY {
+        fn free_var(&mut self, _name: Symbol) -> Option<Rc<Term>> {
+            Some(Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }))
+        }
+        fn captures(&self, name: Symbol) -> bool {
+            name == Symbol::from("y")
+        }
+    }
+
+    #[test]
+    fn test_fold() {
+        // fun y -> x y
+        let term = Term::Abs {
+            param: Symbol::from("y"),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("y"),
+                }),
+            }),
+        };
+        // fun y' -> y y', where y' is fresh
+        let folded = term.fold(&mut ToY);
+        let Term::Abs { param, body } = &folded else {
+            panic!("expected an abstraction");
+        };
+        assert_ne!(*param, Symbol::from("y"));
+        let expected = Term::App {
+            func: Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }),
+            arg: Rc::new(Term::Var { name: *param }),
+        };
+        assert_eq!(**body, expected);
+
+        // fun y -> y is kept as is
+        let term = Term::Abs {
+            param: Symbol::from("y"),
+            body: Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }),
+        };
+        assert_eq!(term.fold(&mut ToY), term);
+    }
 }
 
 #[cfg(test)]
@@ -99,9 +722,9 @@ mod tests_term_free_vars {
     #[test]
     fn test_var() {
         let term = Term::Var {
-            name: String::from("x"),
+            name: Symbol::from("x"),
         };
-        let expected = HashSet::from([String::from("x")]);
+        let expected = HashSet::from([Symbol::from("x")]);
         assert_eq!(term.free_vars(), expected);
     }
 
@@ -109,230 +732,671 @@ mod tests_term_free_vars {
     fn test_app() {
         let term = Term::App {
             func: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
             arg: Rc::new(Term::Var {
-                name: String::from("y"),
+                name: Symbol::from("y"),
             }),
         };
-        let expected = HashSet::from([String::from("x"), String::from("y")]);
+        let expected = HashSet::from([Symbol::from("x"), Symbol::from("y")]);
         assert_eq!(term.free_vars(), expected);
     }
 
     #[test]
     fn test_abs() {
         let term = Term::Abs {
-            param: String::from("x"),
+            param: Symbol::from("x"),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
-        let expected = HashSet::from([String::from("y")]);
+        let expected = HashSet::from([Symbol::from("y")]);
         assert_eq!(term.free_vars(), expected);
     }
 
     #[test]
     fn test_let() {
         let term = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::Var {
-                name: String::from("y"),
+                name: Symbol::from("y"),
             }),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
             }),
         };
-        let expected = HashSet::from([String::from("y"), String::from("z")]);
+        let expected = HashSet::from([Symbol::from("y"), Symbol::from("z")]);
         assert_eq!(term.free_vars(), expected);
 
         let term = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
-        let expected = HashSet::from([String::from("x"), String::from("y")]);
+        let expected = HashSet::from([Symbol::from("x"), Symbol::from("y")]);
         assert_eq!(term.free_vars(), expected);
     }
+
+    #[test]
+    fn test_deep() {
+        // fun x -> ... fun x -> x y
+        let mut term = Term::App {
+            func: Rc::new(Term::Var {
+                name: Symbol::from("x"),
+            }),
+            arg: Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }),
+        };
+        for _ in 0..100_000 {
+            term = Term::Abs {
+                param: Symbol::from("x"),
+                body: Rc::new(term),
+            };
+        }
+        assert_eq!(term.free_vars(), HashSet::from([Symbol::from("y")]));
+    }
 }
 
 #[cfg(test)]
 mod tests_term_subst {
     use super::*;
+    use crate::compiler::parser::parse_term;
+
+    // the term with de Bruijn indices for bound variables, which is the same for alpha-equivalent terms
+    fn nameless(term: &Term, scope: &mut Vec<Symbol>) -> String {
+        match term {
+            Term::Var { name } => match scope.iter().rev().position(|bound| bound == name) {
+                Some(index) => index.to_string(),
+                None => name.to_string(),
+            },
+            Term::App { func, arg } => {
+                format!("({} {})", nameless(func, scope), nameless(arg, scope))
+            }
+            Term::Abs { param, body } => {
+                scope.push(*param);
+                let body = nameless(body, scope);
+                scope.pop();
+                format!("(fun {body})")
+            }
+            Term::Let { name, value, body } => {
+                let value = nameless(value, scope);
+                scope.push(*name);
+                let body = nameless(body, scope);
+                scope.pop();
+                format!("(let {value} {body})")
+            }
+        }
+    }
 
     #[test]
     fn test_var() {
         let term = Term::Var {
-            name: String::from("x"),
+            name: Symbol::from("x"),
         };
         let subst_term = Term::App {
             func: Rc::new(Term::Var {
-                name: String::from("y"),
+                name: Symbol::from("y"),
             }),
             arg: Rc::new(Term::Var {
-                name: String::from("z"),
+                name: Symbol::from("z"),
             }),
         };
-        assert_eq!(term.subst(&String::from("x"), &subst_term), subst_term);
-        assert_eq!(term.subst(&String::from("y"), &subst_term), term);
+        assert_eq!(term.subst(Symbol::from("x"), &subst_term), subst_term);
+        assert_eq!(term.subst(Symbol::from("y"), &subst_term), term);
     }
 
     #[test]
     fn test_app() {
         let term = Term::App {
             func: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
             arg: Rc::new(Term::Var {
-                name: String::from("y"),
+                name: Symbol::from("y"),
             }),
         };
         let subst_term = Term::Var {
-            name: String::from("z"),
+            name: Symbol::from("z"),
         };
 
         let expected = Term::App {
             func: Rc::new(Term::Var {
-                name: String::from("z"),
+                name: Symbol::from("z"),
             }),
             arg: Rc::new(Term::Var {
-                name: String::from("y"),
+                name: Symbol::from("y"),
             }),
         };
-        assert_eq!(term.subst(&String::from("x"), &subst_term), expected);
+        assert_eq!(term.subst(Symbol::from("x"), &subst_term), expected);
 
         let expected = Term::App {
             func: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
             arg: Rc::new(Term::Var {
-                name: String::from("z"),
+                name: Symbol::from("z"),
             }),
         };
-        assert_eq!(term.subst(&String::from("y"), &subst_term), expected);
+        assert_eq!(term.subst(Symbol::from("y"), &subst_term), expected);
     }
 
     #[test]
     fn test_abs() {
         let term = Term::Abs {
-            param: String::from("x"),
+            param: Symbol::from("x"),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
         let subst_term = Term::Var {
-            name: String::from("z"),
+            name: Symbol::from("z"),
         };
 
-        assert_eq!(term.subst(&String::from("x"), &subst_term), term);
+        assert_eq!(term.subst(Symbol::from("x"), &subst_term), term);
 
         let expected = Term::Abs {
-            param: String::from("x"),
+            param: Symbol::from("x"),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
             }),
         };
-        assert_eq!(term.subst(&String::from("y"), &subst_term), expected);
+        assert_eq!(term.subst(Symbol::from("y"), &subst_term), expected);
     }
 
     #[test]
     fn test_let() {
         let term = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
         let subst_term = Term::Var {
-            name: String::from("z"),
+            name: Symbol::from("z"),
         };
 
         let expected = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
-        assert_eq!(term.subst(&String::from("x"), &subst_term), expected);
+        assert_eq!(term.subst(Symbol::from("x"), &subst_term), expected);
 
         let expected = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("z"),
+                }),
+            }),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("z"),
+                }),
+            }),
+        };
+        assert_eq!(term.subst(Symbol::from("y"), &subst_term), expected);
+    }
+
+    #[test]
+    fn test_capture() {
+        let term = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("y"),
+                }),
+            }),
+        };
+        let subst_term = Term::Var {
+            name: Symbol::from("x"),
+        };
+
+        let result = term.subst(Symbol::from("y"), &subst_term);
+        let Term::Abs { param: x0, body: _ } = result else {
+            panic!("not an abstraction: {result:?}");
+        };
+        assert_ne!(x0, Symbol::from("x"));
+        assert_eq!(x0.as_str(), "x");
+        let expected = Term::Abs {
+            param: x0,
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::Var { name: x0 }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+            }),
+        };
+        assert_eq!(result, expected);
+
+        let term = Term::Let {
+            name: Symbol::from("x"),
+            value: Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("y"),
+                }),
+            }),
+        };
+
+        let result = term.subst(Symbol::from("y"), &subst_term);
+        let Term::Let { name: x0, .. } = result else {
+            panic!("not a let: {result:?}");
+        };
+        assert_ne!(x0, Symbol::from("x"));
+        let expected = Term::Let {
+            name: x0,
+            value: Rc::new(Term::Var {
+                name: Symbol::from("x"),
+            }),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::Var { name: x0 }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+            }),
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_nested_shadowing() {
+        // shadowing binders which do not capture the replacing term are kept
+        let term = parse_term("fun x -> x (fun x -> x y) (let x = y in x y)").unwrap();
+        let expected = parse_term("fun x -> x (fun x -> x z) (let x = z in x z)").unwrap();
+        let z = Term::Var {
+            name: Symbol::from("z"),
+        };
+        assert_eq!(term.subst(Symbol::from("y"), &z), expected);
+
+        // the variable is not replaced where it is shadowed
+        let term = parse_term("y (fun y -> y (fun x -> y)) (let y = y in y)").unwrap();
+        let expected = parse_term("z (fun y -> y (fun x -> y)) (let y = z in y)").unwrap();
+        assert_eq!(term.subst(Symbol::from("y"), &z), expected);
+
+        // each shadowing binder which captures the replacing term is renamed apart
+        let term = parse_term("fun x -> x (fun x -> x y) (fun x -> x)").unwrap();
+        let x = Term::Var {
+            name: Symbol::from("x"),
+        };
+        let result = term.subst(Symbol::from("y"), &x);
+        let expected = parse_term("fun x1 -> x1 (fun x2 -> x2 x) (fun x -> x)").unwrap();
+        assert_eq!(
+            nameless(&result, &mut Vec::new()),
+            nameless(&expected, &mut Vec::new())
+        );
+        let Term::Abs { param, body: _ } = &result else {
+            panic!("not an abstraction: {result:?}");
+        };
+        assert_ne!(*param, Symbol::from("x"));
+    }
+
+    #[test]
+    fn test_rename_at_depth() {
+        let term =
+            parse_term("fun a -> fun b -> let c = f in fun f -> c f g (fun g -> g)").unwrap();
+        let renaming = HashMap::from([
+            (Symbol::from("f"), Symbol::from("h")),
+            (Symbol::from("g"), Symbol::from("k")),
+        ]);
+        let expected =
+            parse_term("fun a -> fun b -> let c = h in fun f -> c f k (fun g -> g)").unwrap();
+        assert_eq!(term.rename(&renaming), expected);
+
+        // only the binders whose scope contains a replaced variable are renamed
+        let term =
+            parse_term("fun a -> (fun x -> x) (fun x -> fun b -> fun x -> (fun c -> x y) x)")
+                .unwrap();
+        let x = Term::Var {
+            name: Symbol::from("x"),
+        };
+        let result = term.subst(Symbol::from("y"), &x);
+        let expected =
+            parse_term("fun a -> (fun x -> x) (fun x1 -> fun b -> fun x2 -> (fun c -> x2 x) x2)")
+                .unwrap();
+        assert_eq!(
+            nameless(&result, &mut Vec::new()),
+            nameless(&expected, &mut Vec::new())
+        );
+        let (
+            Term::Abs { param: _, body },
+            Term::Abs {
+                param: _,
+                body: original_body,
+            },
+        ) = (&result, &term)
+        else {
+            panic!("not an abstraction: {result:?}");
+        };
+        let (Term::App { func, arg: _ }, Term::App { func: kept, arg: _ }) =
+            (body.as_ref(), original_body.as_ref())
+        else {
+            panic!("not an application: {body:?}");
+        };
+        assert!(Rc::ptr_eq(func, kept));
+    }
+
+    // binders are renamed under enclosing renamed binders whose occurrences are renamed
+    #[test]
+    fn test_capture_renamed_binders() {
+        let cases = [
+            (
+                "fun x y z x0 v -> let z = x0 (fun x0 -> y x (let x0 = x0 in y)) in \
+                 fun x0 v -> let y = z in x0 v",
+                "fun x y z x0 v x1 v1 -> x1 v1",
+            ),
+            (
+                "fun x y z x0 v -> let x0 = (fun y -> let z = z x0 in x) y in \
+                 fun x -> let x0 = x0 z (v y) in y x0 (x x0)",
+                "fun x y z x0 v x1 -> y (x z (v y)) (x1 (x z (v y)))",
+            ),
+        ];
+        for (term, expected) in cases {
+            let normal = parse_term(term).unwrap().normalize(100, 1000).unwrap();
+            let expected = parse_term(expected).unwrap();
+            assert_eq!(
+                nameless(&normal, &mut Vec::new()),
+                nameless(&expected, &mut Vec::new()),
+                "{term}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_deep() {
+        // fun x -> ... fun x -> x y
+        let deep = |name: &str| {
+            let mut term = Term::App {
+                func: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from(name),
+                }),
+            };
+            for _ in 0..100_000 {
+                term = Term::Abs {
+                    param: Symbol::from("x"),
+                    body: Rc::new(term),
+                };
+            }
+            term
+        };
+        let term = Term::Var {
+            name: Symbol::from("z"),
+        };
+        assert_eq!(deep("y").subst(Symbol::from("y"), &term), deep("z"));
+    }
+}
+
+#[cfg(test)]
+mod tests_term_rename {
+    use super::*;
+
+    #[test]
+    fn test_rename() {
+        // fun x -> x y z
+        let term = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::App {
+                    func: Rc::new(Term::Var {
+                        name: Symbol::from("x"),
+                    }),
+                    arg: Rc::new(Term::Var {
+                        name: Symbol::from("y"),
+                    }),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("z"),
+                }),
+            }),
+        };
+        let renaming = HashMap::from([
+            (Symbol::from("x"), Symbol::from("a")),
+            (Symbol::from("y"), Symbol::from("b")),
+        ]);
+        let expected = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::App {
+                    func: Rc::new(Term::Var {
+                        name: Symbol::from("x"),
+                    }),
+                    arg: Rc::new(Term::Var {
+                        name: Symbol::from("b"),
+                    }),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("z"),
                 }),
             }),
+        };
+        assert_eq!(term.rename(&renaming), expected);
+    }
+}
+
+#[cfg(test)]
+mod tests_term_normalize {
+    use super::*;
+
+    #[test]
+    fn test_normal() {
+        let term = Term::Abs {
+            param: Symbol::from("x"),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
-        assert_eq!(term.subst(&String::from("y"), &subst_term), expected);
+        assert_eq!(term.reduce(), None);
+        assert_eq!(term.normalize(0, 100), Some(term));
+    }
+
+    #[test]
+    fn test_beta() {
+        // (fun x y -> x) y
+        let term = Term::App {
+            func: Rc::new(Term::Abs {
+                param: Symbol::from("x"),
+                body: Rc::new(Term::Abs {
+                    param: Symbol::from("y"),
+                    body: Rc::new(Term::Var {
+                        name: Symbol::from("x"),
+                    }),
+                }),
+            }),
+            arg: Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }),
+        };
+        let result = term.normalize(1, 100).unwrap();
+        let Term::Abs { param: y0, body: _ } = result else {
+            panic!("not an abstraction: {result:?}");
+        };
+        assert_ne!(y0, Symbol::from("y"));
+        let expected = Term::Abs {
+            param: y0,
+            body: Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }),
+        };
+        assert_eq!(result, expected);
+        assert_eq!(term.normalize(0, 100), None);
+    }
+
+    #[test]
+    fn test_let() {
+        // let x = fun y -> y in x z
+        let term = Term::Let {
+            name: Symbol::from("x"),
+            value: Rc::new(Term::Abs {
+                param: Symbol::from("y"),
+                body: Rc::new(Term::Var {
+                    name: Symbol::from("y"),
+                }),
+            }),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("z"),
+                }),
+            }),
+        };
+        let expected = Term::Var {
+            name: Symbol::from("z"),
+        };
+        assert_eq!(term.normalize(2, 100), Some(expected));
+    }
+
+    #[test]
+    fn test_diverge() {
+        // (fun x -> x x) (fun x -> x x)
+        let omega = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::App {
+                func: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+                arg: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+            }),
+        };
+        let term = Term::App {
+            func: Rc::new(omega.clone()),
+            arg: Rc::new(omega),
+        };
+        assert_eq!(term.normalize(100, 100), None);
+    }
+
+    #[test]
+    fn test_deep() {
+        // (fun x -> x) y y ... y
+        let spine = |head: Term, len: usize| {
+            let mut term = head;
+            for _ in 0..len {
+                term = Term::App {
+                    func: Rc::new(term),
+                    arg: Rc::new(Term::Var {
+                        name: Symbol::from("y"),
+                    }),
+                };
+            }
+            term
+        };
+        let id = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::Var {
+                name: Symbol::from("x"),
+            }),
+        };
+        let term = spine(id, 100_000);
+        // y y ... y
+        let expected = spine(
+            Term::Var {
+                name: Symbol::from("y"),
+            },
+            99_999,
+        );
+        assert_eq!(term.normalize(10, 1_000_000), Some(expected));
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Statement {
-    Declaration { name: String, value: Rc<Term> },
+    Declaration {
+        name: Symbol,
+        #[serde(with = "grow")]
+        value: Rc<Term>,
+        #[serde(default)]
+        attrs: Vec<String>,
+        /// The text of the doc comment `(** ... *)` before the declaration.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        doc: Option<String>,
+    },
 }
+
+// the attributes that declarations may be marked with, which are checked by the parser
+// so that misspelled ones are not silently ignored
+pub(super) const EVAL_ATTR: &str = "eval";
+pub(super) const SKI_ATTR: &str = "ski";
+pub(super) const ATTRIBUTES: [&str; 2] = [EVAL_ATTR, SKI_ATTR];
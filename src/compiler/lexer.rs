@@ -0,0 +1,274 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till, take_while},
+    character::complete::{multispace0, satisfy},
+    combinator::{map, recognize, value},
+    error::{Error, ErrorKind},
+    sequence::pair,
+    Finish, IResult,
+};
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+/// A range of bytes in the source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Returns whether the offset is in the span or just after it, where an editor cursor may be.
+    pub fn touches(&self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+
+    /// The smallest span that contains both spans.
+    pub fn union(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+/// Comments and blank lines between tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trivia<'a> {
+    Comment(&'a str),
+    BlankLine,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier,
+    Let,
+    Fun,
+    In,
+    LeftParen,
+    RightParen,
+    Arrow,
+    Equals,
+    Semicolon,
+    /// `\`, which begins an abstraction `\x. body`.
+    Backslash,
+    /// `λ`, which begins an abstraction `λx. body`.
+    Lambda,
+    Dot,
+    /// `[@`, which begins an attribute.
+    AttributeStart,
+    RightBracket,
+    /// The end of the input, which is always the last token.
+    Eof,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Span,
+    /// The comments and blank lines before the token.
+    pub leading: Vec<Trivia<'a>>,
+}
+
+// a comment `(* ... *)`, in which comments may be nested.
+// it fails with `TakeUntil` at its beginning if it is not closed
+fn block_comment(input: &str) -> IResult<&str, &str> {
+    let (mut rest, _) = tag("(*")(input)?;
+    let mut depth = 1;
+    while depth > 0 {
+        if let Some(after) = rest.strip_prefix("(*") {
+            depth += 1;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("*)") {
+            depth -= 1;
+            rest = after;
+        } else {
+            let mut chars = rest.chars();
+            if chars.next().is_none() {
+                return Err(nom::Err::Failure(Error::new(input, ErrorKind::TakeUntil)));
+            }
+            rest = chars.as_str();
+        }
+    }
+    Ok((rest, &input[..input.len() - rest.len()]))
+}
+
+// a comment `-- ...` until the end of the line, which does not include the line break
+fn line_comment(input: &str) -> IResult<&str, &str> {
+    recognize(pair(tag("--"), take_till(|c| c == '\n')))(input)
+}
+
+fn trivia(input: &str) -> IResult<&str, Vec<Trivia<'_>>> {
+    let mut res = Vec::new();
+    let mut input = input;
+    loop {
+        let (rest, spaces) = multispace0(input)?;
+        if spaces.matches('\n').count() >= 2 {
+            res.push(Trivia::BlankLine);
+        }
+        match alt((block_comment, line_comment))(rest) {
+            Ok((rest, text)) => {
+                res.push(Trivia::Comment(text));
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => return Ok((rest, res)),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// identifiers are made of Unicode letters, digits and `_`, and may contain primes after the first character
+// as in OCaml. `λ` begins an abstraction where a name would begin, so it is a letter only after the first character
+fn is_identifier_start(c: char) -> bool {
+    c != 'λ' && (c == '_' || is_xid_start(c))
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    c == '\'' || is_xid_continue(c)
+}
+
+fn word(input: &str) -> IResult<&str, TokenKind> {
+    map(
+        recognize(pair(
+            satisfy(is_identifier_start),
+            take_while(is_identifier_continue),
+        )),
+        |word| match word {
+            "let" => TokenKind::Let,
+            "fun" => TokenKind::Fun,
+            "in" => TokenKind::In,
+            _ => TokenKind::Identifier,
+        },
+    )(input)
+}
+
+fn token_kind(input: &str) -> IResult<&str, TokenKind> {
+    alt((
+        word,
+        value(TokenKind::Arrow, tag("->")),
+        value(TokenKind::LeftParen, tag("(")),
+        value(TokenKind::RightParen, tag(")")),
+        value(TokenKind::Equals, tag("=")),
+        value(TokenKind::Semicolon, tag(";")),
+        value(TokenKind::Backslash, tag("\\")),
+        value(TokenKind::Lambda, tag("λ")),
+        value(TokenKind::Dot, tag(".")),
+        value(TokenKind::AttributeStart, tag("[@")),
+        value(TokenKind::RightBracket, tag("]")),
+    ))(input)
+}
+
+/// Splits the input into tokens, whose spans are relative to the input, followed by an `Eof` token.
+/// The error of an unterminated comment has the kind `TakeUntil` and begins with the comment.
+pub fn tokenize(input: &str) -> Result<Vec<Token<'_>>, Error<&str>> {
+    let mut res = Vec::new();
+    let mut rest = input;
+    loop {
+        let (after_trivia, leading) = trivia(rest).finish()?;
+        let start = input.len() - after_trivia.len();
+        if after_trivia.is_empty() {
+            res.push(Token {
+                kind: TokenKind::Eof,
+                text: after_trivia,
+                span: Span { start, end: start },
+                leading,
+            });
+            return Ok(res);
+        }
+        let (after, kind) = token_kind(after_trivia).finish()?;
+        let end = input.len() - after.len();
+        res.push(Token {
+            kind,
+            text: &input[start..end],
+            span: Span { start, end },
+            leading,
+        });
+        rest = after;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = tokenize("let[@eval] f x= fun y->(x y);").unwrap();
+        let texts: Vec<_> = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(
+            texts,
+            [
+                "let", "[@", "eval", "]", "f", "x", "=", "fun", "y", "->", "(", "x", "y", ")", ";",
+                ""
+            ]
+        );
+        assert_eq!(tokens[7].kind, TokenKind::Fun);
+        assert_eq!(tokens[7].span, Span { start: 16, end: 19 });
+        assert_eq!(tokens[15].span, Span { start: 29, end: 29 });
+        assert_eq!(
+            kinds("letter in_ in"),
+            [
+                TokenKind::Identifier,
+                TokenKind::Identifier,
+                TokenKind::In,
+                TokenKind::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let tokens = tokenize("λf.\\x' y''. f_1 αβ x'").unwrap();
+        let texts: Vec<_> = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(
+            texts,
+            ["λ", "f", ".", "\\", "x'", "y''", ".", "f_1", "αβ", "x'", ""]
+        );
+        assert_eq!(tokens[0].kind, TokenKind::Lambda);
+        assert_eq!(tokens[1].span, Span { start: 2, end: 3 });
+        assert_eq!(tokens[3].kind, TokenKind::Backslash);
+        assert_eq!(tokens[8].kind, TokenKind::Identifier);
+        let tokens = tokenize("let Kλ = λx.xλ").unwrap();
+        let texts: Vec<_> = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(texts, ["let", "Kλ", "=", "λ", "x", ".", "xλ", ""]);
+        assert_eq!(tokenize("'x").unwrap_err().input, "'x");
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = "(* a (* nested *) *)\n-- line (* not a comment\n\n(**)x (* (* *) *) -- end";
+        let tokens = tokenize(input).unwrap();
+        assert_eq!(
+            tokens[0].leading,
+            [
+                Trivia::Comment("(* a (* nested *) *)"),
+                Trivia::Comment("-- line (* not a comment"),
+                Trivia::BlankLine,
+                Trivia::Comment("(**)"),
+            ]
+        );
+        assert_eq!(tokens[0].text, "x");
+        assert_eq!(
+            tokens[1].leading,
+            [Trivia::Comment("(* (* *) *)"), Trivia::Comment("-- end")]
+        );
+        assert_eq!(tokens[1].kind, TokenKind::Eof);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = tokenize("x (* a (* b *) y").unwrap_err();
+        assert_eq!(err.input, "(* a (* b *) y");
+        assert_eq!(err.code, ErrorKind::TakeUntil);
+        assert_eq!(tokenize("x (*)").unwrap_err().code, ErrorKind::TakeUntil);
+        assert_eq!(tokenize("x + y").unwrap_err().input, "+ y");
+    }
+}
@@ -1,152 +1,433 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until},
-    character::complete::{alpha1, alphanumeric1, multispace1},
-    combinator::{eof, map_opt, recognize, success, value},
-    error::{Error, ParseError},
-    multi::{many0, many0_count, many1, separated_list0},
-    sequence::{delimited, pair, tuple},
+    combinator::{cut, map, opt, success},
+    error::{Error, ErrorKind},
+    multi::{many0, many1, separated_list0},
+    sequence::tuple,
     Finish, IResult,
 };
-use once_cell::sync::Lazy;
-use std::{collections::HashSet, rc::Rc};
+use std::rc::Rc;
 
-use super::lambda::{Statement, Term};
+use super::{
+    lambda::{Statement, Term, ATTRIBUTES},
+    lexer::{self, Token, TokenKind},
+    symbol::Symbol,
+};
+
+pub use super::lexer::{Span, Trivia};
+
+// the parser reads the tokens of the lexer, which always end with `Eof`
+type Tokens<'a> = &'a [Token<'a>];
 
-fn comment<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, (), E> {
-    value((), tuple((tag("(*"), take_until("*)"), tag("*)"))))(input)
+fn token<'a>(kind: TokenKind) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, &'a Token<'a>> {
+    move |input: Tokens<'a>| match input.split_first() {
+        Some((token, rest)) if token.kind == kind => Ok((rest, token)),
+        _ => Err(nom::Err::Error(Error::new(input, ErrorKind::Tag))),
+    }
 }
 
-fn ws<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, (), E> {
-    value((), many0_count(alt((value((), multispace1), comment))))(input)
+// an error of the parser, which begins with the token where parsing failed
+fn source_error<'a>(input: &'a str, err: Error<Tokens<'_>>) -> Error<&'a str> {
+    let start = err
+        .input
+        .first()
+        .map_or(input.len(), |token| token.span.start);
+    Error::new(&input[start..], err.code)
 }
 
-fn token<'a, F: 'a, O, E: ParseError<&'a str>>(
-    inner: F,
-) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
-where
-    F: Fn(&'a str) -> IResult<&'a str, O, E>,
-{
-    delimited(ws, inner, ws)
+fn parse_tokens<O>(
+    input: &str,
+    parser: for<'t> fn(Tokens<'t>) -> IResult<Tokens<'t>, O>,
+) -> Result<O, Error<&str>> {
+    let tokens = lexer::tokenize(input)?;
+    parser(&tokens)
+        .finish()
+        .map(|(_, output)| output)
+        .map_err(|err| source_error(input, err))
 }
 
-static RESERVED_WORDS: Lazy<HashSet<&str>> = Lazy::new(|| HashSet::from(["fun", "let", "in"]));
+/// The spans of the subterms of a term, in the order they are left by [`Term::walk`].
+/// It is empty for a term that does not come from the source.
+pub type Spans = Vec<Span>;
+
+// the beginning of a term, which is followed by its subterms if any.
+// keywords and parentheses are kept for the positions of the terms they begin
+#[derive(Clone)]
+enum Opening<'a> {
+    Var(&'a Token<'a>),
+    Parens(&'a Token<'a>),
+    Abs(&'a Token<'a>, Vec<&'a Token<'a>>),
+    Let(&'a Token<'a>, &'a Token<'a>, Vec<&'a Token<'a>>),
+}
 
-fn is_reserved_word(input: &str) -> bool {
-    RESERVED_WORDS.contains(input)
+fn aterm_opening(input: Tokens<'_>) -> IResult<Tokens<'_>, Opening<'_>> {
+    alt((
+        map(token(TokenKind::Identifier), Opening::Var),
+        map(token(TokenKind::LeftParen), Opening::Parens),
+    ))(input)
 }
 
-fn identifier(input: &str) -> IResult<&str, &str> {
-    map_opt(
-        recognize(pair(
-            alt((alpha1, tag("_"))),
-            many0_count(alt((alphanumeric1, tag("_")))),
+fn term_opening(input: Tokens<'_>) -> IResult<Tokens<'_>, Opening<'_>> {
+    alt((aterm_opening, term_abs_opening, term_let_opening))(input)
+}
+
+// `fun x y -> body`, or `\x y. body` and `λx y. body` as written in the literature
+fn term_abs_opening(input: Tokens<'_>) -> IResult<Tokens<'_>, Opening<'_>> {
+    let params = || many1(token(TokenKind::Identifier));
+    let (input, (keyword, params, _)) = alt((
+        tuple((token(TokenKind::Fun), params(), token(TokenKind::Arrow))),
+        tuple((
+            alt((token(TokenKind::Backslash), token(TokenKind::Lambda))),
+            params(),
+            token(TokenKind::Dot),
         )),
-        |id| {
-            if !is_reserved_word(id) {
-                Some(id)
-            } else {
-                None
-            }
-        },
-    )(input)
+    ))(input)?;
+    success(Opening::Abs(keyword, params))(input)
 }
 
-fn parens<'a, F: 'a, O, E: ParseError<&'a str> + 'a>(
-    inner: F,
-) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
-where
-    F: Fn(&'a str) -> IResult<&'a str, O, E>,
-{
-    delimited(token(tag("(")), inner, token(tag(")")))
+fn term_let_opening(input: Tokens<'_>) -> IResult<Tokens<'_>, Opening<'_>> {
+    let (input, keyword) = token(TokenKind::Let)(input)?;
+    let (input, name) = token(TokenKind::Identifier)(input)?;
+    let (input, params) = many0(token(TokenKind::Identifier))(input)?;
+    let (input, _) = token(TokenKind::Equals)(input)?;
+    success(Opening::Let(keyword, name, params))(input)
 }
 
-fn term(input: &str) -> IResult<&str, Term> {
-    alt((term_app, term_abs, term_let, parens(term)))(input)
+// constructs enclosing the subterm being parsed, with the offsets where they begin
+enum Frame<'a> {
+    // `func (`, waiting for the argument
+    App(Term, Span),
+    // `(`, waiting for `)`
+    Parens(usize),
+    // `fun params ->`, waiting for the body
+    Abs(usize, Vec<&'a Token<'a>>),
+    // `let name params =`, waiting for the value
+    LetValue(usize, &'a Token<'a>, Vec<&'a Token<'a>>),
+    // `let name = value in`, waiting for the body
+    LetBody(usize, &'a Token<'a>, Term),
 }
 
-fn aterm(input: &str) -> IResult<&str, Term> {
-    alt((term_var, parens(term)))(input)
+// the terms are given with their spans, which include the parentheses around them
+enum State {
+    // expecting a term
+    Term,
+    // an atomic term has been parsed
+    Atom(Term, Span),
+    // expecting more arguments of an application
+    App(Term, Span),
+    // a term has been parsed, which completes the innermost frame
+    Reduce(Term, Span),
 }
 
-fn term_var(input: &str) -> IResult<&str, Term> {
-    let (input, name) = token(identifier)(input)?;
-    let result = Term::Var {
-        name: String::from(name),
-    };
-    success(result)(input)
-}
-
-fn term_app(input: &str) -> IResult<&str, Term> {
-    let (input, func) = aterm(input)?;
-    let (input, args) = many0(aterm)(input)?;
-    let result = args.into_iter().fold(func, |acc, arg| Term::App {
-        func: Rc::new(acc),
-        arg: Rc::new(arg),
-    });
-    success(result)(input)
-}
-
-fn term_abs(input: &str) -> IResult<&str, Term> {
-    let (input, _) = token(tag("fun"))(input)?;
-    let (input, params) = many1(token(identifier))(input)?;
-    let (input, _) = token(tag("->"))(input)?;
-    let (input, body) = term(input)?;
-    let result = params.iter().rev().fold(body, |acc, &param| Term::Abs {
-        param: String::from(param),
-        body: Rc::new(acc),
-    });
-    success(result)(input)
-}
-
-fn term_let(input: &str) -> IResult<&str, Term> {
-    let (input, _) = token(tag("let"))(input)?;
-    let (input, name) = token(identifier)(input)?;
-    let (input, params) = many0(token(identifier))(input)?;
-    let (input, _) = token(tag("="))(input)?;
-    let (input, value) = term(input)?;
-    let (input, _) = token(tag("in"))(input)?;
-    let (input, body) = term(input)?;
-    let result = Term::Let {
-        name: String::from(name),
-        value: Rc::new(params.iter().rev().fold(value, |acc, &param| Term::Abs {
-            param: String::from(param),
-            body: Rc::new(acc),
-        })),
-        body: Rc::new(body),
+// the offset of a part of the input from its beginning
+fn offset(input: &str, part: &str) -> usize {
+    part.as_ptr() as usize - input.as_ptr() as usize
+}
+
+// abstracts the body over the parameters, where the outermost abstraction begins at `start`
+// and each inner one at its parameter
+fn abs(start: usize, params: &[&Token], body: Term, end: usize, spans: &mut Spans) -> Term {
+    params
+        .iter()
+        .enumerate()
+        .rev()
+        .fold(body, |acc, (i, param)| {
+            let start = if i == 0 { start } else { param.span.start };
+            spans.push(Span { start, end });
+            Term::Abs {
+                param: Symbol::from(param.text),
+                body: Rc::new(acc),
+            }
+        })
+}
+
+// parses with an explicit stack of frames instead of recursion, so that terms may be nested arbitrarily deep.
+// the spans of the subterms are recorded as the subterms are completed,
+// which is the order they are left by `Term::walk`
+fn term(input: Tokens<'_>) -> IResult<Tokens<'_>, (Term, Spans)> {
+    let mut input = input;
+    let mut frames = Vec::new();
+    let mut spans = Vec::new();
+    let mut state = State::Term;
+    let var = |name: &Token, spans: &mut Spans| {
+        spans.push(name.span);
+        let term = Term::Var {
+            name: Symbol::from(name.text),
+        };
+        (term, name.span)
     };
-    success(result)(input)
+    loop {
+        state = match state {
+            State::Term => {
+                let (rest, opening) = term_opening(input)?;
+                input = rest;
+                match opening {
+                    Opening::Var(name) => {
+                        let (term, span) = var(name, &mut spans);
+                        State::Atom(term, span)
+                    }
+                    Opening::Parens(paren) => {
+                        frames.push(Frame::Parens(paren.span.start));
+                        State::Term
+                    }
+                    Opening::Abs(keyword, params) => {
+                        frames.push(Frame::Abs(keyword.span.start, params));
+                        State::Term
+                    }
+                    Opening::Let(keyword, name, params) => {
+                        frames.push(Frame::LetValue(keyword.span.start, name, params));
+                        State::Term
+                    }
+                }
+            }
+            State::Atom(atom, span) => match frames.pop() {
+                Some(Frame::App(func, func_span)) => {
+                    let span = func_span.union(span);
+                    spans.push(span);
+                    let term = Term::App {
+                        func: Rc::new(func),
+                        arg: Rc::new(atom),
+                    };
+                    State::App(term, span)
+                }
+                frame => {
+                    frames.extend(frame);
+                    State::App(atom, span)
+                }
+            },
+            State::App(func, func_span) => match opt(aterm_opening)(input)? {
+                (rest, Some(Opening::Var(name))) => {
+                    input = rest;
+                    let (arg, arg_span) = var(name, &mut spans);
+                    let span = func_span.union(arg_span);
+                    spans.push(span);
+                    let term = Term::App {
+                        func: Rc::new(func),
+                        arg: Rc::new(arg),
+                    };
+                    State::App(term, span)
+                }
+                (rest, Some(Opening::Parens(paren))) => {
+                    input = rest;
+                    frames.push(Frame::App(func, func_span));
+                    frames.push(Frame::Parens(paren.span.start));
+                    State::Term
+                }
+                (_, Some(_)) => unreachable!("atomic terms are variables or parenthesized"),
+                (_, None) => State::Reduce(func, func_span),
+            },
+            State::Reduce(term, span) => match frames.pop() {
+                None => return success((term, spans))(input),
+                Some(Frame::App(_, _)) => unreachable!(),
+                Some(Frame::Parens(start)) => {
+                    let (rest, paren) = token(TokenKind::RightParen)(input)?;
+                    input = rest;
+                    State::Atom(
+                        term,
+                        Span {
+                            start,
+                            end: paren.span.end,
+                        },
+                    )
+                }
+                Some(Frame::Abs(start, params)) => {
+                    let term = abs(start, &params, term, span.end, &mut spans);
+                    State::Reduce(
+                        term,
+                        Span {
+                            start,
+                            end: span.end,
+                        },
+                    )
+                }
+                Some(Frame::LetValue(start, name, params)) => {
+                    let (rest, _) = token(TokenKind::In)(input)?;
+                    input = rest;
+                    let value = match params.first() {
+                        Some(param) => abs(param.span.start, &params, term, span.end, &mut spans),
+                        None => term,
+                    };
+                    frames.push(Frame::LetBody(start, name, value));
+                    State::Term
+                }
+                Some(Frame::LetBody(start, name, value)) => {
+                    let span = Span {
+                        start,
+                        end: span.end,
+                    };
+                    spans.push(span);
+                    let term = Term::Let {
+                        name: Symbol::from(name.text),
+                        value: Rc::new(value),
+                        body: Rc::new(term),
+                    };
+                    State::Reduce(term, span)
+                }
+            },
+        }
+    }
 }
 
-fn stmt(input: &str) -> IResult<&str, Statement> {
+fn stmt(input: Tokens<'_>) -> IResult<Tokens<'_>, (Statement, Spans)> {
     stmt_decl(input)
 }
 
-fn stmt_decl(input: &str) -> IResult<&str, Statement> {
-    let (input, _) = token(tag("let"))(input)?;
-    let (input, name) = token(identifier)(input)?;
-    let (input, params) = many0(token(identifier))(input)?;
-    let (input, _) = token(tag("="))(input)?;
-    let (input, value) = term(input)?;
+// an attribute `[@name]`, which fails with `Verify` at the name if it is unknown
+fn attribute(input: Tokens<'_>) -> IResult<Tokens<'_>, &Token<'_>> {
+    let (input, _) = token(TokenKind::AttributeStart)(input)?;
+    let (rest, name) = token(TokenKind::Identifier)(input)?;
+    if !ATTRIBUTES.contains(&name.text) {
+        return Err(nom::Err::Failure(Error::new(input, ErrorKind::Verify)));
+    }
+    let (rest, _) = token(TokenKind::RightBracket)(rest)?;
+    Ok((rest, name))
+}
+
+// the text of a doc comment `(** ... *)` without the blank lines around it,
+// where `(**)` and comments starting with `(***` are ordinary comments
+fn doc_text(comment: &str) -> Option<String> {
+    let text = comment.strip_prefix("(**")?.strip_suffix("*)")?;
+    if text.starts_with('*') {
+        return None;
+    }
+    let lines: Vec<_> = text.lines().map(str::trim).collect();
+    let start = lines.iter().position(|line| !line.is_empty())?;
+    let end = lines.iter().rposition(|line| !line.is_empty())?;
+    Some(lines[start..=end].join("\n"))
+}
+
+// the doc comment of a statement, which is the last comment before it unless a blank line follows the comment
+fn doc_comment(leading: &[Trivia]) -> Option<String> {
+    match leading.last() {
+        Some(Trivia::Comment(comment)) => doc_text(comment),
+        Some(Trivia::BlankLine) | None => None,
+    }
+}
+
+// a declaration, which fails where it is invalid once it begins with `let`
+// instead of backtracking to its beginning
+fn stmt_decl(input: Tokens<'_>) -> IResult<Tokens<'_>, (Statement, Spans)> {
+    let (input, keyword) = token(TokenKind::Let)(input)?;
+    cut(|input| stmt_decl_rest(input, keyword))(input)
+}
+
+fn stmt_decl_rest<'a>(
+    input: Tokens<'a>,
+    keyword: &Token<'_>,
+) -> IResult<Tokens<'a>, (Statement, Spans)> {
+    let (input, attrs) = many0(attribute)(input)?;
+    let (input, name) = token(TokenKind::Identifier)(input)?;
+    let (input, params) = many0(token(TokenKind::Identifier))(input)?;
+    let (input, _) = token(TokenKind::Equals)(input)?;
+    let (input, (value, mut spans)) = term(input)?;
+    let value = match (params.first(), spans.last()) {
+        (Some(param), Some(&span)) => abs(param.span.start, &params, value, span.end, &mut spans),
+        _ => value,
+    };
     let result = Statement::Declaration {
-        name: String::from(name),
-        value: Rc::new(params.iter().rev().fold(value, |acc, &param| Term::Abs {
-            param: String::from(param),
-            body: Rc::new(acc),
-        })),
+        name: Symbol::from(name.text),
+        value: Rc::new(value),
+        attrs: attrs.into_iter().map(|attr| attr.text.into()).collect(),
+        doc: doc_comment(&keyword.leading),
     };
-    success(result)(input)
+    success((result, spans))(input)
 }
 
-fn program(input: &str) -> IResult<&str, Vec<Statement>> {
-    let (input, stmts) = separated_list0(many1(token(tag(";"))), stmt)(input)?;
-    let (input, _) = many0(token(tag(";")))(input)?;
-    let (input, _) = eof(input)?;
+fn program(input: Tokens<'_>) -> IResult<Tokens<'_>, Vec<(Statement, Spans)>> {
+    let (input, stmts) = separated_list0(many1(token(TokenKind::Semicolon)), stmt)(input)?;
+    let (input, _) = many0(token(TokenKind::Semicolon))(input)?;
+    let (input, _) = token(TokenKind::Eof)(input)?;
     success(stmts)(input)
 }
 
 pub fn parse(input: &str) -> Result<Vec<Statement>, Error<&str>> {
-    program(input).finish().map(|(_, output)| output)
+    parse_tokens(input, program).map(|output| output.into_iter().map(|(stmt, _)| stmt).collect())
+}
+
+/// Parses a program along with the spans of the subterms of each declaration.
+pub fn parse_with_spans(input: &str) -> Result<(Vec<Statement>, Vec<Spans>), Error<&str>> {
+    parse_tokens(input, program).map(|output| output.into_iter().unzip())
+}
+
+fn whole_term(input: Tokens<'_>) -> IResult<Tokens<'_>, Term> {
+    let (input, (term, _)) = term(input)?;
+    let (input, _) = token(TokenKind::Eof)(input)?;
+    success(term)(input)
+}
+
+/// Parses a term on its own, outside of any declaration.
+pub fn parse_term(input: &str) -> Result<Term, Error<&str>> {
+    parse_tokens(input, whole_term)
+}
+
+/// A statement along with its source text and the trivia around it.
+#[derive(Debug)]
+pub struct Item<'a> {
+    pub leading: Vec<Trivia<'a>>,
+    pub stmt: Statement,
+    /// The source text of the statement without the terminating `;`, which may contain comments.
+    pub source: &'a str,
+    /// A comment following the statement on the same line.
+    pub trailing: Option<&'a str>,
+}
+
+/// Parses a program into statements with the comments and blank lines around them, for formatting.
+/// Also returns the trivia after the last statement.
+pub fn parse_items(input: &str) -> Result<(Vec<Item<'_>>, Vec<Trivia<'_>>), Error<&str>> {
+    // the program is validated by the ordinary parser, which items are only a different view of
+    parse(input)?;
+    let tokens = lexer::tokenize(input)?;
+    let mut res = Vec::new();
+    let mut rest = &tokens[..];
+    let mut leading = rest[0].leading.clone();
+    while rest[0].kind != TokenKind::Eof {
+        let start = rest[0].span.start;
+        let (after, (stmt, _)) = stmt(rest)
+            .finish()
+            .map_err(|err| source_error(input, err))?;
+        let end = tokens.len() - after.len();
+        rest = &tokens[end..];
+        let mut source_end = tokens[end - 1].span.end;
+        // a comment on the same line as the end of the statement or its first `;` is a trailing comment
+        let mut line_end = source_end;
+        let mut trivia = Vec::new();
+        if let [semicolon, after @ ..] = rest {
+            if semicolon.kind == TokenKind::Semicolon {
+                // comments before the `;` belong to the statement
+                for trivia in &semicolon.leading {
+                    if let Trivia::Comment(comment) = trivia {
+                        source_end = offset(input, comment) + comment.len();
+                    }
+                }
+                line_end = semicolon.span.end;
+                rest = after;
+            }
+        }
+        while let [semicolon, after @ ..] = rest {
+            if semicolon.kind != TokenKind::Semicolon {
+                break;
+            }
+            trivia.extend(semicolon.leading.iter().cloned());
+            rest = after;
+        }
+        trivia.extend(rest[0].leading.iter().cloned());
+        let trailing = match trivia.first() {
+            Some(&Trivia::Comment(comment))
+                if !input[line_end..offset(input, comment)].contains('\n') =>
+            {
+                trivia.remove(0);
+                Some(comment)
+            }
+            _ => None,
+        };
+        res.push(Item {
+            leading: std::mem::replace(&mut leading, trivia),
+            stmt,
+            source: &input[start..source_end],
+            trailing,
+        });
+    }
+    Ok((res, leading))
 }
 
 #[cfg(test)]
@@ -162,64 +443,270 @@ mod tests {
           let bar = x (y z);
           let baz = fun x y -> x y;
           let qux = let f x = y in z;
+          let[@eval] quux = x;
         "};
         let output = parse(input).unwrap();
         let expected = vec![
             Statement::Declaration {
-                name: String::from("foo"),
+                name: Symbol::from("foo"),
                 value: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
+                attrs: vec![],
+                doc: None,
             },
             Statement::Declaration {
-                name: String::from("bar"),
+                name: Symbol::from("bar"),
                 value: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("x"),
+                        name: Symbol::from("x"),
                     }),
                     arg: Rc::new(Term::App {
                         func: Rc::new(Term::Var {
-                            name: String::from("y"),
+                            name: Symbol::from("y"),
                         }),
                         arg: Rc::new(Term::Var {
-                            name: String::from("z"),
+                            name: Symbol::from("z"),
                         }),
                     }),
                 }),
+                attrs: vec![],
+                doc: None,
             },
             Statement::Declaration {
-                name: String::from("baz"),
+                name: Symbol::from("baz"),
                 value: Rc::new(Term::Abs {
-                    param: String::from("x"),
+                    param: Symbol::from("x"),
                     body: Rc::new(Term::Abs {
-                        param: String::from("y"),
+                        param: Symbol::from("y"),
                         body: Rc::new(Term::App {
                             func: Rc::new(Term::Var {
-                                name: String::from("x"),
+                                name: Symbol::from("x"),
                             }),
                             arg: Rc::new(Term::Var {
-                                name: String::from("y"),
+                                name: Symbol::from("y"),
                             }),
                         }),
                     }),
                 }),
+                attrs: vec![],
+                doc: None,
             },
             Statement::Declaration {
-                name: String::from("qux"),
+                name: Symbol::from("qux"),
                 value: Rc::new(Term::Let {
-                    name: String::from("f"),
+                    name: Symbol::from("f"),
                     value: Rc::new(Term::Abs {
-                        param: String::from("x"),
+                        param: Symbol::from("x"),
                         body: Rc::new(Term::Var {
-                            name: String::from("y"),
+                            name: Symbol::from("y"),
                         }),
                     }),
                     body: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                 }),
+                attrs: vec![],
+                doc: None,
+            },
+            Statement::Declaration {
+                name: Symbol::from("quux"),
+                value: Rc::new(Term::Var {
+                    name: Symbol::from("x"),
+                }),
+                attrs: vec![String::from("eval")],
+                doc: None,
             },
         ];
         assert_eq!(output, expected);
     }
+
+    #[test]
+    fn test_parse_with_spans() {
+        let input = "let F x = fun y -> (f x) y;\nlet G = let z = F in z";
+        let (program, spans) = parse_with_spans(input).unwrap();
+        assert_eq!(program, parse(input).unwrap());
+        let texts: Vec<Vec<_>> = spans
+            .iter()
+            .map(|spans| {
+                spans
+                    .iter()
+                    .map(|span| &input[span.start..span.end])
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                vec![
+                    "f",
+                    "x",
+                    "f x",
+                    "y",
+                    "(f x) y",
+                    "fun y -> (f x) y",
+                    "x = fun y -> (f x) y"
+                ],
+                vec!["F", "z", "let z = F in z"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let input = indoc! {"
+            (** The identity.
+                Returns its argument. *)
+            let Id x = x;
+            (** detached *)
+
+            let K x y = x;
+            (* not a doc comment *)
+            let S x y z = x z (y z);
+            (**) let I = Id; (*** banner *)
+            (** last *) let B = S (K S) K
+        "};
+        let docs: Vec<_> = parse(input)
+            .unwrap()
+            .into_iter()
+            .map(|stmt| match stmt {
+                Statement::Declaration {
+                    name: _,
+                    value: _,
+                    attrs: _,
+                    doc,
+                } => doc,
+            })
+            .collect();
+        assert_eq!(
+            docs,
+            [
+                Some("The identity.\nReturns its argument.".into()),
+                None,
+                None,
+                None,
+                Some("last".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let expected = parse("let Two = fun f x -> f (f x)").unwrap();
+        assert_eq!(parse("let Two = \\f x. f (f x)").unwrap(), expected);
+        assert_eq!(parse("let Two = λf. λx. f (f x)").unwrap(), expected);
+        assert_eq!(
+            parse("let Swap' = \\x. fun y -> (λf. f y) x").unwrap(),
+            parse("let Swap' = fun x y -> (fun f -> f y) x").unwrap()
+        );
+        let input = "let Δ = \\x. x x; let Ω = Δ Δ";
+        let (program, spans) = parse_with_spans(input).unwrap();
+        assert_eq!(program[1].to_string(), "let Ω = Δ Δ;");
+        let span = spans[0].last().unwrap();
+        assert_eq!(&input[span.start..span.end], "\\x. x x");
+        assert!(parse("let Id = \\x -> x").is_err());
+        assert!(parse("let Id = fun x. x").is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = indoc! {"
+            (* let Old = (* nested *) x; *)
+            let foo = x -- comment
+              (* inline *) y;
+            -- let bar = z;
+            let baz = (* (* *) *) z
+        "};
+        assert_eq!(
+            parse(input).unwrap(),
+            parse("let foo = x y; let baz = z").unwrap()
+        );
+        let err = parse("let foo = x (* (* *) y").unwrap_err();
+        assert_eq!(err.input, "(* (* *) y");
+        assert_eq!(err.code, ErrorKind::TakeUntil);
+    }
+
+    // errors are at the token where a declaration stops being valid, not at its beginning
+    #[test]
+    fn test_error_positions() {
+        assert_eq!(parse("let F = x;\nlet = x").unwrap_err().input, "= x");
+        assert_eq!(parse("let F = x (y;").unwrap_err().input, ";");
+        assert_eq!(parse("let F x y = ").unwrap_err().input, "");
+        assert_eq!(parse("let F = x y)").unwrap_err().input, ")");
+    }
+
+    #[test]
+    fn test_unknown_attribute() {
+        let err = parse("let[@ski] Foo = x;\nlet[@evl] Bar = y").unwrap_err();
+        assert_eq!(err.input, "evl] Bar = y");
+        assert_eq!(err.code, ErrorKind::Verify);
+    }
+
+    #[test]
+    fn test_items() {
+        let input = indoc! {"
+            (* header *)
+
+            let foo = x (* before *); (* trailing *)
+            -- leading
+            let bar = y;;
+            -- end
+        "};
+        let (items, trailing) = parse_items(input).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0].leading,
+            [Trivia::Comment("(* header *)"), Trivia::BlankLine]
+        );
+        assert_eq!(items[0].source, "let foo = x (* before *)");
+        assert_eq!(items[0].trailing, Some("(* trailing *)"));
+        assert_eq!(items[1].leading, [Trivia::Comment("-- leading")]);
+        assert_eq!(items[1].source, "let bar = y");
+        assert_eq!(items[1].trailing, None);
+        assert_eq!(trailing, [Trivia::Comment("-- end")]);
+    }
+
+    #[test]
+    fn test_deep() {
+        let depth = 100_000;
+        let inputs = [
+            // f x x ... x
+            (format!("let foo = f{};", " x".repeat(depth)), 2 * depth + 1),
+            // f (f ... (f x))
+            (
+                format!("let foo = {}x{};", "f (".repeat(depth), ")".repeat(depth)),
+                2 * depth + 1,
+            ),
+            // fun x -> ... fun x -> x
+            (
+                format!("let foo = {}x;", "fun x -> ".repeat(depth)),
+                depth + 1,
+            ),
+            // let x = x in ... let x = x in x
+            (
+                format!("let foo = {}x;", "let x = x in ".repeat(depth)),
+                2 * depth + 1,
+            ),
+            // let x = (let x = ... in x) in x
+            (
+                format!(
+                    "let foo = {}x{};",
+                    "let x = (".repeat(depth),
+                    ") in x".repeat(depth)
+                ),
+                2 * depth + 1,
+            ),
+        ];
+        for (input, size) in inputs {
+            let output = parse(&input).unwrap();
+            let Statement::Declaration {
+                name: _,
+                value,
+                attrs: _,
+                doc: _,
+            } = &output[0];
+            assert_eq!(output.len(), 1);
+            assert_eq!(value.size(), size);
+        }
+    }
 }
@@ -0,0 +1,416 @@
+use std::{collections::HashSet, fmt, rc::Rc};
+
+use super::{
+    core::{Arena, Core, Node},
+    lambda::Statement,
+    parser::Spans,
+};
+
+/// A property of core terms that passes rely on and establish.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Invariant {
+    /// Functions and arguments of applications contain no abstractions or lets.
+    BinderFreeApps,
+    /// Bodies of abstractions contain no lets.
+    LetFreeAbsBodies,
+    /// Values of lets are not lets.
+    UnnestedLets,
+}
+
+impl Invariant {
+    pub fn name(self) -> &'static str {
+        match self {
+            Invariant::BinderFreeApps => "binder-free-apps",
+            Invariant::LetFreeAbsBodies => "let-free-abs-bodies",
+            Invariant::UnnestedLets => "unnested-lets",
+        }
+    }
+
+    fn holds_at(self, arena: &Arena, node: Node) -> bool {
+        match (self, *arena.get(node)) {
+            (Invariant::BinderFreeApps, Core::App { func, arg }) => [func, arg]
+                .iter()
+                .all(|&part| !arena.contains_abs(part) && !arena.contains_let(part)),
+            (Invariant::LetFreeAbsBodies, Core::Abs { hint: _, body }) => !arena.contains_let(body),
+            (
+                Invariant::UnnestedLets,
+                Core::Let {
+                    hint: _,
+                    value,
+                    body: _,
+                },
+            ) => !matches!(
+                arena.get(value),
+                Core::Let {
+                    hint: _,
+                    value: _,
+                    body: _,
+                }
+            ),
+            _ => true,
+        }
+    }
+
+    /// Checks the invariant at every subterm of `node`.
+    pub fn holds(self, arena: &Arena, node: Node) -> bool {
+        // subterms are shared, so each one is checked only once
+        let mut visited = HashSet::new();
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node) {
+                continue;
+            }
+            if !self.holds_at(arena, node) {
+                return false;
+            }
+            match *arena.get(node) {
+                Core::BVar { index: _ } | Core::FVar { name: _ } => {}
+                Core::App { func, arg } => stack.extend([func, arg]),
+                Core::Abs { hint: _, body } => stack.push(body),
+                Core::Let {
+                    hint: _,
+                    value,
+                    body,
+                } => stack.extend([value, body]),
+            }
+        }
+        true
+    }
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A transformation of core terms.
+/// A pass may assume the invariants it requires, and must establish the ones it ensures
+/// while preserving those established by the passes before it.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    fn requires(&self) -> &[Invariant];
+    fn ensures(&self) -> &[Invariant];
+    fn run(&self, arena: &mut Arena, node: Node) -> Node;
+}
+
+struct Entry {
+    pass: Box<dyn Pass>,
+    enabled: bool,
+}
+
+/// The program as it was after a pass.
+pub struct Dump {
+    pub pass: &'static str,
+    pub program: Vec<Statement>,
+}
+
+/// Passes run in the order they are registered, each of which can be enabled or disabled by name.
+#[derive(Default)]
+pub struct Pipeline {
+    entries: Vec<Entry>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Adds an enabled pass at the end of the pipeline.
+    pub fn register(&mut self, pass: impl Pass + 'static) {
+        assert!(
+            self.entry(pass.name()).is_err(),
+            "pass {} is already registered",
+            pass.name()
+        );
+        self.entries.push(Entry {
+            pass: Box::new(pass),
+            enabled: true,
+        });
+    }
+
+    /// Names of all registered passes, in order.
+    pub fn pass_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.entries.iter().map(|entry| entry.pass.name())
+    }
+
+    fn entry(&self, name: &str) -> Result<usize, String> {
+        self.entries
+            .iter()
+            .position(|entry| entry.pass.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = self.pass_names().collect();
+                format!(
+                    "unknown pass: {name} (expected one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+
+    /// Looks up the name of a registered pass.
+    pub fn pass_name(&self, name: &str) -> Result<&'static str, String> {
+        self.entry(name).map(|i| self.entries[i].pass.name())
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let i = self.entry(name)?;
+        self.entries[i].enabled = enabled;
+        Ok(())
+    }
+
+    /// Enables or disables passes from a configuration with lines of the form `<pass> = true` or `<pass> = false`.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn configure(&mut self, config: &str) -> Result<(), String> {
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |msg: String| format!("line {}: {msg}", i + 1);
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `<pass> = true|false`: {line}")))?;
+            let enabled = match value.trim() {
+                "true" => true,
+                "false" => false,
+                value => return Err(error(format!("expected true or false: {value}"))),
+            };
+            self.set_enabled(name.trim(), enabled).map_err(error)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the invariants required by each enabled pass, and finally by `requires`,
+    /// are established by the enabled passes before it.
+    pub fn check(&self, requires: &[Invariant]) -> Result<(), String> {
+        let mut established = HashSet::new();
+        for entry in self.entries.iter().filter(|entry| entry.enabled) {
+            let pass = &entry.pass;
+            if let Some(inv) = pass
+                .requires()
+                .iter()
+                .find(|inv| !established.contains(*inv))
+            {
+                return Err(format!(
+                    "pass {} requires {inv}, which no enabled pass before it ensures",
+                    pass.name()
+                ));
+            }
+            established.extend(pass.ensures().iter().copied());
+        }
+        if let Some(inv) = requires.iter().find(|inv| !established.contains(*inv)) {
+            return Err(format!(
+                "code generation requires {inv}, which no enabled pass ensures"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs the enabled passes on a program, also returning the intermediate programs after the passes named in `dump_after`.
+    /// In debug builds, the invariants established so far are verified after each pass.
+    pub fn run(&self, program: &[Statement], dump_after: &[&str]) -> (Vec<Statement>, Vec<Dump>) {
+        let (program, _, dumps) = self.run_with_spans(program, &[], dump_after);
+        (program, dumps)
+    }
+
+    /// Like [`Pipeline::run`], but also traces the terms of the resulting program back to the spans
+    /// of the terms they are made from.
+    /// `spans` are those of each declaration, where missing ones are empty.
+    pub fn run_with_spans(
+        &self,
+        program: &[Statement],
+        spans: &[Spans],
+        dump_after: &[&str],
+    ) -> (Vec<Statement>, Vec<Spans>, Vec<Dump>) {
+        // declarations share one arena, so that common subterms are stored only once
+        let mut arena = Arena::new();
+        let mut nodes: Vec<Node> = program
+            .iter()
+            .enumerate()
+            .map(|(i, stmt)| match stmt {
+                Statement::Declaration {
+                    name: _,
+                    value,
+                    attrs: _,
+                    doc: _,
+                } => {
+                    let spans = spans.get(i).map_or(&[][..], Vec::as_slice);
+                    arena.add_term_with_spans(value, spans)
+                }
+            })
+            .collect();
+        let mut established = Vec::new();
+        let mut dumps = Vec::new();
+        for entry in self.entries.iter().filter(|entry| entry.enabled) {
+            let pass = &entry.pass;
+            for node in &mut nodes {
+                *node = pass.run(&mut arena, *node);
+            }
+            established.extend(pass.ensures().iter().copied());
+            if cfg!(debug_assertions) {
+                for &inv in &established {
+                    for &node in &nodes {
+                        assert!(
+                            inv.holds(&arena, node),
+                            "{inv} does not hold after pass {}",
+                            pass.name()
+                        );
+                    }
+                }
+            }
+            if dump_after.contains(&pass.name()) {
+                dumps.push(Dump {
+                    pass: pass.name(),
+                    program: to_program(&arena, program, &nodes),
+                });
+            }
+        }
+        // names are reconstructed so that let-bound names never shadow each other
+        let spans = nodes.iter().map(|&node| arena.spans(node)).collect();
+        (to_program(&arena, program, &nodes), spans, dumps)
+    }
+}
+
+fn to_program(arena: &Arena, program: &[Statement], nodes: &[Node]) -> Vec<Statement> {
+    program
+        .iter()
+        .zip(nodes)
+        .map(|(stmt, node)| match stmt {
+            Statement::Declaration {
+                name,
+                value: _,
+                attrs,
+                doc,
+            } => Statement::Declaration {
+                name: *name,
+                value: Rc::new(arena.to_term(*node)),
+                attrs: attrs.clone(),
+                doc: doc.clone(),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests_invariant {
+    use super::*;
+
+    #[test]
+    fn test_holds() {
+        let mut arena = Arena::new();
+        let x = arena.fvar("x");
+        let var = arena.bvar(0);
+        let id = arena.abs("x", var);
+        let app = arena.app(x, id);
+        let let_ = arena.let_("y", x, var);
+        let abs_let = arena.abs("z", let_);
+        let let_let = arena.let_("w", let_, var);
+        assert!(Invariant::BinderFreeApps.holds(&arena, abs_let));
+        assert!(!Invariant::BinderFreeApps.holds(&arena, app));
+        assert!(Invariant::LetFreeAbsBodies.holds(&arena, app));
+        assert!(!Invariant::LetFreeAbsBodies.holds(&arena, abs_let));
+        assert!(Invariant::UnnestedLets.holds(&arena, abs_let));
+        assert!(!Invariant::UnnestedLets.holds(&arena, let_let));
+    }
+}
+
+#[cfg(test)]
+mod tests_pipeline {
+    use super::*;
+    use crate::compiler::parser::parse;
+
+    // replaces every abstraction by a variable
+    struct Erase;
+
+    impl Pass for Erase {
+        fn name(&self) -> &'static str {
+            "erase"
+        }
+        fn requires(&self) -> &[Invariant] {
+            &[]
+        }
+        fn ensures(&self) -> &[Invariant] {
+            &[Invariant::BinderFreeApps, Invariant::LetFreeAbsBodies]
+        }
+        fn run(&self, arena: &mut Arena, node: Node) -> Node {
+            if arena.contains_abs(node) {
+                arena.fvar("erased")
+            } else {
+                node
+            }
+        }
+    }
+
+    // claims to establish unnested-lets without doing anything
+    struct Broken;
+
+    impl Pass for Broken {
+        fn name(&self) -> &'static str {
+            "broken"
+        }
+        fn requires(&self) -> &[Invariant] {
+            &[Invariant::LetFreeAbsBodies]
+        }
+        fn ensures(&self) -> &[Invariant] {
+            &[Invariant::UnnestedLets]
+        }
+        fn run(&self, _: &mut Arena, node: Node) -> Node {
+            node
+        }
+    }
+
+    fn pipeline() -> Pipeline {
+        let mut pipeline = Pipeline::new();
+        pipeline.register(Erase);
+        pipeline.register(Broken);
+        pipeline
+    }
+
+    #[test]
+    fn test_run() {
+        let program = parse("let foo = fun x -> x; let bar = x y;").unwrap();
+        let (output, dumps) = pipeline().run(&program, &["erase"]);
+        let expected = parse("let foo = erased; let bar = x y;").unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(dumps.len(), 1);
+        assert_eq!(dumps[0].pass, "erase");
+        assert_eq!(dumps[0].program, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "unnested-lets does not hold after pass broken")]
+    fn test_run_broken() {
+        let program = parse("let foo = let x = (let y = z in y) in x;").unwrap();
+        pipeline().run(&program, &[]);
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut pipeline = pipeline();
+        assert_eq!(
+            pipeline.pass_names().collect::<Vec<_>>(),
+            ["erase", "broken"]
+        );
+        assert!(pipeline.check(&[Invariant::UnnestedLets]).is_ok());
+        pipeline
+            .configure("# no nested lets\n\nbroken = false\n")
+            .unwrap();
+        assert_eq!(
+            pipeline.check(&[Invariant::UnnestedLets]),
+            Err(String::from(
+                "code generation requires unnested-lets, which no enabled pass ensures"
+            ))
+        );
+        pipeline.set_enabled("broken", true).unwrap();
+        pipeline.set_enabled("erase", false).unwrap();
+        assert_eq!(
+            pipeline.check(&[]),
+            Err(String::from(
+                "pass broken requires let-free-abs-bodies, which no enabled pass before it ensures"
+            ))
+        );
+        assert!(pipeline.configure("broken = maybe").is_err());
+        assert!(pipeline.configure("mangle = false").is_err());
+        assert!(pipeline.set_enabled("mangle", false).is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use std::{collections::HashMap, fmt};
+
+use super::{
+    lambda::{Statement, Term, Visitor},
+    symbol::Symbol,
+};
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+// the position where a subterm appears, which determines whether it needs parentheses
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pos {
+    Term,
+    Head,
+    Arg,
+}
+
+fn needs_parens(term: &Term, pos: Pos) -> bool {
+    match term {
+        Term::Var { name: _ } => false,
+        Term::App { func: _, arg: _ } => pos == Pos::Arg,
+        Term::Abs { param: _, body: _ } => pos != Pos::Term,
+        Term::Let {
+            name: _,
+            value: _,
+            body: _,
+        } => pos != Pos::Term,
+    }
+}
+
+fn name_width(name: Symbol) -> usize {
+    name.as_str().chars().count()
+}
+
+// splits `fun x y -> body` into its parameters and body
+fn params(term: &Term) -> (Vec<Symbol>, &Term) {
+    let mut params = Vec::new();
+    let mut term = term;
+    while let Term::Abs { param, body } = term {
+        params.push(*param);
+        term = body;
+    }
+    (params, term)
+}
+
+// splits `f x y` into its head and arguments
+fn spine(term: &Term) -> (&Term, Vec<&Term>) {
+    let mut args = Vec::new();
+    let mut term = term;
+    while let Term::App { func, arg } = term {
+        args.push(arg.as_ref());
+        term = func;
+    }
+    args.reverse();
+    (term, args)
+}
+
+// widths of subterms printed on a single line without parentheses, keyed by their addresses
+#[derive(Default)]
+struct FlatWidths(HashMap<*const Term, usize>);
+
+impl FlatWidths {
+    fn get(&self, term: &Term, pos: Pos) -> usize {
+        let width = self.0[&(term as *const Term)];
+        if needs_parens(term, pos) {
+            width + 2
+        } else {
+            width
+        }
+    }
+}
+
+impl Visitor<'_> for FlatWidths {
+    fn leave(&mut self, term: &Term) {
+        let width = match term {
+            Term::Var { name } => name_width(*name),
+            // func arg
+            Term::App { func, arg } => self.get(func, Pos::Head) + 1 + self.get(arg, Pos::Arg),
+            Term::Abs { param, body } => match body.as_ref() {
+                // fun param params -> body
+                Term::Abs { param: _, body: _ } => {
+                    name_width(*param) + 1 + self.get(body, Pos::Term)
+                }
+                // fun param -> body
+                _ => name_width(*param) + 8 + self.get(body, Pos::Term),
+            },
+            Term::Let { name, value, body } => match value.as_ref() {
+                // let name params = value in body
+                Term::Abs { param: _, body: _ } => {
+                    name_width(*name) + self.get(value, Pos::Term) + 4 + self.get(body, Pos::Term)
+                }
+                // let name = value in body
+                _ => {
+                    name_width(*name) + self.get(value, Pos::Term) + 11 + self.get(body, Pos::Term)
+                }
+            },
+        };
+        self.0.insert(term, width);
+    }
+}
+
+enum Task<'a> {
+    Text(&'a str),
+    Newline(usize),
+    // a term at the given indentation, broken into lines if it does not fit
+    Term(&'a Term, usize, Pos),
+    // a term on the current line
+    Flat(&'a Term, Pos),
+    // the body of `fun`, on the same line if it fits
+    Body(&'a Term, usize),
+    // the value of `let` and `in`, on the same line if they fit
+    LetValue(&'a Term, usize),
+    // the value of a declaration and `;`, on the same line if they fit
+    DeclValue(&'a Term),
+}
+
+// lays out terms greedily: a term is printed on the current line if it fits in the width,
+// and otherwise its parts are placed on separate lines.
+// tasks are kept on an explicit stack, so that terms may be nested arbitrarily deep.
+struct Printer<'a> {
+    width: usize,
+    widths: FlatWidths,
+    tasks: Vec<Task<'a>>,
+    out: String,
+    col: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(width: usize) -> Self {
+        Printer {
+            width,
+            widths: FlatWidths::default(),
+            tasks: Vec::new(),
+            out: String::new(),
+            col: 0,
+        }
+    }
+
+    fn fits(&self, width: usize) -> bool {
+        self.col + width <= self.width
+    }
+
+    fn run(&mut self) {
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Text(text) => {
+                    self.out.push_str(text);
+                    self.col += text.chars().count();
+                }
+                Task::Newline(indent) => {
+                    self.out.push('\n');
+                    self.out.extend(std::iter::repeat_n(' ', indent));
+                    self.col = indent;
+                }
+                Task::Term(term, indent, pos) => {
+                    let flat = self.fits(self.widths.get(term, pos));
+                    self.push_term(term, indent, pos, flat);
+                }
+                Task::Flat(term, pos) => self.push_term(term, 0, pos, true),
+                Task::Body(body, indent) => {
+                    if self.fits(1 + self.widths.get(body, Pos::Term)) {
+                        self.tasks.push(Task::Flat(body, Pos::Term));
+                        self.tasks.push(Task::Text(" "));
+                    } else {
+                        self.tasks.push(Task::Term(body, indent, Pos::Term));
+                        self.tasks.push(Task::Newline(indent));
+                    }
+                }
+                Task::LetValue(value, indent) => {
+                    if self.fits(1 + self.widths.get(value, Pos::Term) + 3) {
+                        self.tasks.push(Task::Text(" in"));
+                        self.tasks.push(Task::Flat(value, Pos::Term));
+                        self.tasks.push(Task::Text(" "));
+                    } else {
+                        self.tasks.push(Task::Text("in"));
+                        self.tasks.push(Task::Newline(indent));
+                        self.tasks.push(Task::Term(value, indent + 2, Pos::Term));
+                        self.tasks.push(Task::Newline(indent + 2));
+                    }
+                }
+                Task::DeclValue(value) => {
+                    if self.fits(1 + self.widths.get(value, Pos::Term) + 1) {
+                        self.tasks.push(Task::Text(";"));
+                        self.tasks.push(Task::Flat(value, Pos::Term));
+                        self.tasks.push(Task::Text(" "));
+                    } else {
+                        self.tasks.push(Task::Text(";"));
+                        self.tasks.push(Task::Term(value, 2, Pos::Term));
+                        self.tasks.push(Task::Newline(2));
+                    }
+                }
+            }
+        }
+    }
+
+    // pushes the tasks to print a term, in reverse order
+    fn push_term(&mut self, term: &'a Term, indent: usize, pos: Pos, flat: bool) {
+        if needs_parens(term, pos) {
+            self.tasks.push(Task::Text(")"));
+            self.push_term(term, indent + 1, Pos::Term, flat);
+            self.tasks.push(Task::Text("("));
+            return;
+        }
+        match term {
+            Term::Var { name } => self.tasks.push(Task::Text(name.as_str())),
+            Term::App { func: _, arg: _ } => {
+                let (head, args) = spine(term);
+                for arg in args.into_iter().rev() {
+                    if flat {
+                        self.tasks.push(Task::Flat(arg, Pos::Arg));
+                        self.tasks.push(Task::Text(" "));
+                    } else {
+                        self.tasks.push(Task::Term(arg, indent + 2, Pos::Arg));
+                        self.tasks.push(Task::Newline(indent + 2));
+                    }
+                }
+                if flat {
+                    self.tasks.push(Task::Flat(head, Pos::Head));
+                } else {
+                    self.tasks.push(Task::Term(head, indent, Pos::Head));
+                }
+            }
+            Term::Abs { param: _, body: _ } => {
+                let (params, body) = params(term);
+                if flat {
+                    self.tasks.push(Task::Flat(body, Pos::Term));
+                    self.tasks.push(Task::Text(" "));
+                } else {
+                    self.tasks.push(Task::Body(body, indent + 2));
+                }
+                self.tasks.push(Task::Text(" ->"));
+                self.push_params(&params);
+                self.tasks.push(Task::Text("fun"));
+            }
+            Term::Let { name, value, body } => {
+                let (params, value) = params(value);
+                if flat {
+                    self.tasks.push(Task::Flat(body, Pos::Term));
+                    self.tasks.push(Task::Text(" in "));
+                    self.tasks.push(Task::Flat(value, Pos::Term));
+                    self.tasks.push(Task::Text(" "));
+                } else {
+                    self.tasks.push(Task::Term(body, indent, Pos::Term));
+                    self.tasks.push(Task::Newline(indent));
+                    self.tasks.push(Task::LetValue(value, indent));
+                }
+                self.tasks.push(Task::Text(" ="));
+                self.push_params(&params);
+                self.tasks.push(Task::Text(name.as_str()));
+                self.tasks.push(Task::Text("let "));
+            }
+        }
+    }
+
+    fn push_params(&mut self, params: &[Symbol]) {
+        for param in params.iter().rev() {
+            self.tasks.push(Task::Text(param.as_str()));
+            self.tasks.push(Task::Text(" "));
+        }
+    }
+}
+
+/// Prints a term in the syntax accepted by the parser, breaking lines to fit in `width` columns where possible.
+pub fn print_term(term: &Term, width: usize) -> String {
+    let mut printer = Printer::new(width);
+    term.walk(&mut printer.widths);
+    printer.tasks.push(Task::Term(term, 0, Pos::Term));
+    printer.run();
+    printer.out
+}
+
+/// Prints a statement, including the terminating `;`.
+pub fn print_statement(stmt: &Statement, width: usize) -> String {
+    let mut printer = Printer::new(width);
+    match stmt {
+        // doc comments are kept by the formatter along with the other comments
+        Statement::Declaration {
+            name,
+            value,
+            attrs,
+            doc: _,
+        } => {
+            value.walk(&mut printer.widths);
+            let (params, value) = params(value);
+            printer.tasks.push(Task::DeclValue(value));
+            printer.tasks.push(Task::Text(" ="));
+            printer.push_params(&params);
+            printer.tasks.push(Task::Text(name.as_str()));
+            printer.tasks.push(Task::Text(" "));
+            for attr in attrs.iter().rev() {
+                printer.tasks.push(Task::Text("]"));
+                printer.tasks.push(Task::Text(attr));
+                printer.tasks.push(Task::Text("[@"));
+            }
+            printer.tasks.push(Task::Text("let"));
+        }
+    }
+    printer.run();
+    printer.out
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&print_term(self, DEFAULT_WIDTH))
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&print_statement(self, DEFAULT_WIDTH))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::compiler::parser::parse;
+
+    fn print_program(program: &[Statement], width: usize) -> String {
+        program
+            .iter()
+            .map(|stmt| print_statement(stmt, width) + "\n")
+            .collect()
+    }
+
+    #[test]
+    fn test_print() {
+        let input = indoc! {"
+            let foo = x;
+            let bar = x (y z) (fun x -> x);
+            let baz = fun x y -> x y;
+            let qux = let f x = y in (let g = z in g) (fun x -> x);
+            let[@eval] [@ski] quux x = (fun y -> y) x;
+        "};
+        let expected = indoc! {"
+            let foo = x;
+            let bar = x (y z) (fun x -> x);
+            let baz x y = x y;
+            let qux = let f x = y in (let g = z in g) (fun x -> x);
+            let[@eval][@ski] quux x = (fun y -> y) x;
+        "};
+        let program = parse(input).unwrap();
+        assert_eq!(print_program(&program, DEFAULT_WIDTH), expected);
+    }
+
+    #[test]
+    fn test_print_narrow() {
+        let input = indoc! {"
+            let Fix f = (fun x -> f (fun y -> x x y)) (fun x -> f (fun y -> x x y));
+            let Factorial n = let f = Fix (fun f n r -> (IsZero n) r (f (Pred n) (Mul n r))) in f n One;
+        "};
+        let expected = indoc! {"
+            let Fix f =
+              (fun x -> f (fun y -> x x y))
+                (fun x -> f (fun y -> x x y));
+            let Factorial n =
+              let f =
+                Fix
+                  (fun f n r ->
+                     IsZero
+                       n
+                       r
+                       (f (Pred n) (Mul n r)))
+              in
+              f n One;
+        "};
+        let program = parse(input).unwrap();
+        let output = print_program(&program, 40);
+        assert_eq!(output, expected);
+        assert!(output.lines().all(|line| line.len() <= 40));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = indoc! {"
+            let Id x = x;
+            let Fix f = (fun x -> f (fun y -> x x y)) (fun x -> f (fun y -> x x y));
+            let Pred n = fun f x -> n (fun g h -> h (g f)) ((fun x y -> x) x) Id;
+            let[@eval] Factorial n = let f = Fix (fun f n r -> (n (fun x -> False) True) r (f (Pred n) (Mul n r))) in f n One;
+            let Nested = let x = (let y = a in let z = (fun w -> w) y in z) in (let v = x in v) x;
+        "};
+        let program = parse(input).unwrap();
+        for width in [0, 10, 20, 40, DEFAULT_WIDTH, usize::MAX] {
+            let output = print_program(&program, width);
+            assert_eq!(parse(&output).unwrap(), program, "width {width}:\n{output}");
+        }
+    }
+
+    #[test]
+    fn test_deep() {
+        let depth = 100_000;
+        // nested arguments are indented further at each level, so only spines and chains are deep here
+        let inputs = [
+            format!("let foo = f{};", " x".repeat(depth)),
+            format!("let foo = {}x;", "let x = x in ".repeat(depth)),
+        ];
+        for input in inputs {
+            let program = parse(&input).unwrap();
+            let output = print_program(&program, DEFAULT_WIDTH);
+            assert_eq!(parse(&output).unwrap(), program);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::{fs, rc::Rc};
+
+use super::{
+    diagnostics::Diagnostic,
+    generator,
+    lambda::{Statement, Term},
+    parser,
+    pipeline::Pipeline,
+    printer, transformer,
+};
+
+const HELP: &str = "\
+let <name> <params> = <term>;  declare a name, and show its code and normal form
+<term>                         show the normal form of a term
+:type <term>                   show the TypeScript code of a term or a declared name
+:ast <term>                    show the syntax tree of a term or a declared name as JSON
+:normalized <term>             show a term or a declared name after the transformer passes
+:load <file>                   declare the names in a file
+:help                          show this help
+:quit                          exit";
+
+/// An interactive session, in which names are declared one at a time.
+pub struct Repl {
+    program: Vec<Statement>,
+    pipeline: Pipeline,
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl {
+            program: Vec::new(),
+            pipeline: transformer::pipeline(),
+        }
+    }
+}
+
+fn decl_name(stmt: &Statement) -> &str {
+    match stmt {
+        Statement::Declaration {
+            name,
+            value: _,
+            attrs: _,
+            doc: _,
+        } => name.as_str(),
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl::default()
+    }
+
+    /// Evaluates a line of input, and returns the text to show.
+    pub fn eval(&mut self, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(String::new());
+        }
+        let Some(command) = input.strip_prefix(':') else {
+            return self.eval_source(input);
+        };
+        let (command, arg) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        let arg = arg.trim();
+        match command {
+            "type" => {
+                let stmt = self.subject(arg)?;
+                Ok(generator::generate_statements(&self.transform(stmt)))
+            }
+            "ast" => {
+                let stmt = self.subject(arg)?;
+                Ok(serde_json::to_string_pretty(&stmt).expect("failed to serialize") + "\n")
+            }
+            "normalized" => {
+                let stmt = self.subject(arg)?;
+                let mut res = String::new();
+                for stmt in self.transform(stmt) {
+                    res.push_str(&printer::print_statement(&stmt, printer::DEFAULT_WIDTH));
+                    res.push('\n');
+                }
+                Ok(res)
+            }
+            "load" => {
+                let source = fs::read_to_string(arg).map_err(|err| format!("{arg}: {err}"))?;
+                let program = parser::parse(&source).map_err(|err| {
+                    format!("{arg}:{}", Diagnostic::from_parse_error(&source, &err))
+                })?;
+                let names: Vec<_> = program.iter().map(decl_name).collect();
+                let res = format!("declared {}\n", names.join(", "));
+                for stmt in program {
+                    self.declare(stmt);
+                }
+                Ok(res)
+            }
+            "help" => Ok(format!("{HELP}\n")),
+            _ => Err(format!("unknown command: :{command} (see :help)")),
+        }
+    }
+
+    // declarations are shown with their code and normal forms, and terms with their normal forms
+    fn eval_source(&mut self, input: &str) -> Result<String, String> {
+        let mut res = String::new();
+        match parser::parse(input) {
+            Ok(program) => {
+                for stmt in program {
+                    res.push_str(&generator::generate_statements(
+                        &self.transform(stmt.clone()),
+                    ));
+                    let (name, value) = match &stmt {
+                        Statement::Declaration {
+                            name,
+                            value,
+                            attrs: _,
+                            doc: _,
+                        } => (*name, Rc::clone(value)),
+                    };
+                    self.declare(stmt);
+                    res.push_str(&format!("{name} = {}\n", self.normal_form(&value)));
+                }
+            }
+            Err(_) => {
+                let term = parser::parse_term(input)
+                    .map_err(|err| Diagnostic::from_parse_error(input, &err).to_string())?;
+                res.push_str(&format!("{}\n", self.normal_form(&term)));
+            }
+        }
+        Ok(res)
+    }
+
+    // a redeclared name replaces the earlier declaration
+    fn declare(&mut self, stmt: Statement) {
+        match self
+            .program
+            .iter_mut()
+            .find(|decl| decl_name(decl) == decl_name(&stmt))
+        {
+            Some(decl) => *decl = stmt,
+            None => self.program.push(stmt),
+        }
+    }
+
+    fn normal_form(&self, term: &Term) -> String {
+        match transformer::evaluate(&self.program, term) {
+            Some(normal) => printer::print_term(&normal, printer::DEFAULT_WIDTH),
+            None => String::from("(no normal form found)"),
+        }
+    }
+
+    // the declaration of a name, or a term declared as `it`
+    fn subject(&self, arg: &str) -> Result<Statement, String> {
+        if let Some(stmt) = self.program.iter().find(|stmt| decl_name(stmt) == arg) {
+            return Ok(stmt.clone());
+        }
+        let term = parser::parse_term(arg)
+            .map_err(|err| Diagnostic::from_parse_error(arg, &err).to_string())?;
+        Ok(Statement::Declaration {
+            name: "it".into(),
+            value: Rc::new(term),
+            attrs: vec![],
+            doc: None,
+        })
+    }
+
+    fn transform(&self, stmt: Statement) -> Vec<Statement> {
+        self.pipeline.run(&[stmt], &[]).0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    #[test]
+    fn test_declare() {
+        let mut repl = Repl::new();
+        let expected = indoc! {r#"
+            interface Id extends Fun { ret: Id$1<this["arg"]> }
+            type Id$1<x> = x;
+            Id = fun x -> x
+        "#};
+        assert_eq!(repl.eval("let Id x = x;").unwrap(), expected);
+        let expected = indoc! {"
+            type Foo = App<Id, y>;
+            Foo = (no normal form found)
+        "};
+        assert_eq!(repl.eval("let Foo = Id y").unwrap(), expected);
+        assert_eq!(repl.eval("Id (fun y -> y y)").unwrap(), "fun y -> y y\n");
+        // redeclared names replace earlier ones
+        repl.eval("let Id x = x x;").unwrap();
+        assert_eq!(repl.eval("Id").unwrap(), "fun x -> x x\n");
+        assert_eq!(repl.eval("").unwrap(), "");
+        assert_eq!(
+            repl.eval("let = x;").unwrap_err(),
+            "1:5: syntax error near `=`"
+        );
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut repl = Repl::new();
+        repl.eval("let Id x = x;").unwrap();
+        assert_eq!(
+            repl.eval(":type Id Id").unwrap(),
+            "type it = App<Id, Id>;\n"
+        );
+        assert_eq!(
+            repl.eval(":type Id").unwrap(),
+            "interface Id extends Fun { ret: Id$1<this[\"arg\"]> }\ntype Id$1<x> = x;\n"
+        );
+        let expected = indoc! {r#"
+            {
+              "declaration": {
+                "name": "it",
+                "value": {
+                  "var": {
+                    "name": "x"
+                  }
+                },
+                "attrs": []
+              }
+            }
+        "#};
+        assert_eq!(repl.eval(":ast x").unwrap(), expected);
+        assert_eq!(
+            repl.eval(":normalized fun x -> Id (fun y -> y) x").unwrap(),
+            "let it = let v y = y in fun x -> Id v x;\n"
+        );
+        assert!(repl.eval(":help").unwrap().contains(":load <file>"));
+        assert!(repl.eval(":frobnicate").is_err());
+        assert!(repl.eval(":load /nonexistent.ml").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let mut repl = Repl::new();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/factorial.ml");
+        let res = repl.eval(&format!(":load {path}")).unwrap();
+        assert!(res.starts_with("declared Id, Const, Fix, "));
+        assert_eq!(repl.eval("Mul One One").unwrap(), "fun f x -> f x\n");
+    }
+}
@@ -0,0 +1,381 @@
+use std::{collections::HashSet, rc::Rc};
+
+use super::{
+    lambda::{Statement, Term, Visitor, SKI_ATTR},
+    symbol::Symbol,
+};
+
+/// The names of the combinators that translated declarations refer to, which are distinct from every name
+/// in the program, so that they are neither shadowed nor abstracted away with a parameter of the same name.
+/// Backends define them before the program with fixed definitions of their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Combinators {
+    s: Symbol,
+    k: Symbol,
+    i: Symbol,
+    b: Symbol,
+    c: Symbol,
+}
+
+impl Combinators {
+    fn new(program: &[Statement]) -> Combinators {
+        #[derive(Default)]
+        struct Names(HashSet<Symbol>);
+        impl Visitor<'_> for Names {
+            fn var(&mut self, name: Symbol) {
+                self.0.insert(name);
+            }
+            fn bind(&mut self, name: Symbol) {
+                self.0.insert(name);
+            }
+        }
+        let mut names = Names::default();
+        for stmt in program {
+            let Statement::Declaration {
+                name,
+                value,
+                attrs: _,
+                doc: _,
+            } = stmt;
+            names.0.insert(*name);
+            value.walk(&mut names);
+        }
+        let fresh = |base: &str| {
+            let mut name = String::from(base);
+            while names.0.contains(&Symbol::intern(&name)) {
+                name.push('_');
+            }
+            Symbol::intern(&name)
+        };
+        Combinators {
+            s: fresh("S"),
+            k: fresh("K"),
+            i: fresh("I"),
+            b: fresh("B"),
+            c: fresh("C"),
+        }
+    }
+
+    /// The names of `S`, `K`, `I`, `B` and `C` in this order.
+    pub fn names(&self) -> [Symbol; 5] {
+        [self.s, self.k, self.i, self.b, self.c]
+    }
+}
+
+fn var(name: Symbol) -> Rc<Term> {
+    Rc::new(Term::Var { name })
+}
+
+fn app(func: Rc<Term>, arg: Rc<Term>) -> Rc<Term> {
+    Rc::new(Term::App { func, arg })
+}
+
+// `[x] M` of a term made only of variables and applications, with the usual optimizations:
+// `[x] x = I`, `[x] M = K M` and `[x] (M x) = M` where `x` is not free in `M`,
+// and `[x] (M N)` is `B M ([x] N)`, `C ([x] M) N` or `S ([x] M) ([x] N)` depending on where `x` is free
+fn abstract_var(param: Symbol, term: &Term, combinators: &Combinators) -> Rc<Term> {
+    enum Abstracted {
+        // the parameter itself
+        Param,
+        // a term in which the parameter is not free
+        Const(Rc<Term>),
+        // the abstraction of a term in which the parameter is free
+        Abs(Rc<Term>),
+    }
+    struct Abstract<'a> {
+        param: Symbol,
+        combinators: &'a Combinators,
+        stack: Vec<Abstracted>,
+    }
+    impl Abstract<'_> {
+        fn abs(&self, abstracted: Abstracted) -> Rc<Term> {
+            match abstracted {
+                Abstracted::Param => var(self.combinators.i),
+                Abstracted::Const(term) => app(var(self.combinators.k), term),
+                Abstracted::Abs(term) => term,
+            }
+        }
+    }
+    impl Visitor<'_> for Abstract<'_> {
+        fn leave(&mut self, term: &Term) {
+            let abstracted = match term {
+                Term::Var { name } if *name == self.param => Abstracted::Param,
+                Term::Var { name } => Abstracted::Const(var(*name)),
+                Term::App { func: _, arg: _ } => {
+                    let arg = self.stack.pop().expect("missing argument");
+                    let func = self.stack.pop().expect("missing function");
+                    let combinators = self.combinators;
+                    match (func, arg) {
+                        (Abstracted::Const(func), Abstracted::Const(arg)) => {
+                            Abstracted::Const(app(func, arg))
+                        }
+                        (Abstracted::Const(func), Abstracted::Param) => Abstracted::Abs(func),
+                        (Abstracted::Const(func), arg) => {
+                            Abstracted::Abs(app(app(var(combinators.b), func), self.abs(arg)))
+                        }
+                        (func, Abstracted::Const(arg)) => {
+                            Abstracted::Abs(app(app(var(combinators.c), self.abs(func)), arg))
+                        }
+                        (func, arg) => Abstracted::Abs(app(
+                            app(var(combinators.s), self.abs(func)),
+                            self.abs(arg),
+                        )),
+                    }
+                }
+                Term::Abs { param: _, body: _ }
+                | Term::Let {
+                    name: _,
+                    value: _,
+                    body: _,
+                } => unreachable!("binders are abstracted from the innermost"),
+            };
+            self.stack.push(abstracted);
+        }
+    }
+    let mut visitor = Abstract {
+        param,
+        combinators,
+        stack: Vec::new(),
+    };
+    term.walk(&mut visitor);
+    let abstracted = visitor.stack.pop().expect("missing term");
+    visitor.abs(abstracted)
+}
+
+// translates a term into applications of combinators, where `let x = M in N` is `(fun x -> N) M`
+fn translate_term(term: &Term, combinators: &Combinators) -> Rc<Term> {
+    struct Translate<'a> {
+        combinators: &'a Combinators,
+        stack: Vec<Rc<Term>>,
+    }
+    impl Visitor<'_> for Translate<'_> {
+        fn leave(&mut self, term: &Term) {
+            let translated = match term {
+                Term::Var { name } => var(*name),
+                Term::App { func: _, arg: _ } => {
+                    let arg = self.stack.pop().expect("missing argument");
+                    let func = self.stack.pop().expect("missing function");
+                    app(func, arg)
+                }
+                Term::Abs { param, body: _ } => {
+                    let body = self.stack.pop().expect("missing body");
+                    abstract_var(*param, &body, self.combinators)
+                }
+                Term::Let {
+                    name,
+                    value: _,
+                    body: _,
+                } => {
+                    let body = self.stack.pop().expect("missing body");
+                    let value = self.stack.pop().expect("missing value");
+                    app(abstract_var(*name, &body, self.combinators), value)
+                }
+            };
+            self.stack.push(translated);
+        }
+    }
+    let mut visitor = Translate {
+        combinators,
+        stack: Vec::new(),
+    };
+    term.walk(&mut visitor);
+    visitor.stack.pop().expect("missing term")
+}
+
+/// Translates declarations into applications of the combinators `S`, `K`, `I`, `B` and `C` by bracket abstraction,
+/// and returns the names of the combinators if any declaration is translated, which are then free in the program.
+/// Only declarations marked with `[@ski]` are translated unless `translate_all` is set.
+/// The names of the combinators are suffixed with `_` if they are taken by the program.
+pub fn translate(
+    program: &[Statement],
+    translate_all: bool,
+) -> (Vec<Statement>, Option<Combinators>) {
+    let is_translated =
+        |attrs: &[String]| translate_all || attrs.iter().any(|attr| attr == SKI_ATTR);
+    if !program.iter().any(|stmt| match stmt {
+        Statement::Declaration {
+            name: _,
+            value: _,
+            attrs,
+            doc: _,
+        } => is_translated(attrs),
+    }) {
+        return (program.to_vec(), None);
+    }
+    let combinators = Combinators::new(program);
+    let res = program
+        .iter()
+        .map(|stmt| {
+            let Statement::Declaration {
+                name,
+                value,
+                attrs,
+                doc,
+            } = stmt;
+            let value = if is_translated(attrs) {
+                translate_term(value, &combinators)
+            } else {
+                Rc::clone(value)
+            };
+            Statement::Declaration {
+                name: *name,
+                value,
+                attrs: attrs.clone(),
+                doc: doc.clone(),
+            }
+        })
+        .collect();
+    (res, Some(combinators))
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::{
+        compiler::{
+            parser::{parse, parse_term},
+            transformer,
+        },
+        CompileOptions,
+    };
+
+    // the combinators as declarations, which define what the definitions of the backends compute
+    const DEFINITIONS: &str = "\
+        let S x y z = x z (y z);
+        let K x y = x;
+        let I x = x;
+        let B x y z = x (y z);
+        let C x y z = x z y;";
+
+    fn declarations(program: &[Statement]) -> Vec<String> {
+        program.iter().map(|stmt| stmt.to_string()).collect()
+    }
+
+    #[test]
+    fn test_translate() {
+        let program = parse(indoc! {"
+            let [@ski] Swap x y = y x;
+            let [@ski] Twice f x = f (f x);
+            let [@ski] Dup = let d x = x x in d d;
+            let Id x = x
+        "})
+        .unwrap();
+        let (translated, combinators) = translate(&program, false);
+        assert_eq!(
+            declarations(&translated),
+            [
+                "let[@ski] Swap = C I;",
+                "let[@ski] Twice = S B I;",
+                "let[@ski] Dup = S I I (S I I);",
+                "let Id x = x;",
+            ]
+        );
+        assert_eq!(
+            combinators.map(|combinators| combinators.names()),
+            Some(["S", "K", "I", "B", "C"].map(Symbol::from))
+        );
+        assert_eq!(
+            translate(&program[3..], false),
+            (program[3..].to_vec(), None)
+        );
+    }
+
+    #[test]
+    fn test_combinator_names() {
+        let program = parse("let S = K; let F I = fun x -> I x x").unwrap();
+        let (translated, combinators) = translate(&program, true);
+        assert_eq!(
+            declarations(&translated),
+            ["let S = K;", "let F = C S_ I_;"]
+        );
+        assert_eq!(
+            combinators.map(|combinators| combinators.names()),
+            Some(["S_", "K_", "I_", "B", "C"].map(Symbol::from))
+        );
+    }
+
+    // the output is made of the definitions of the combinators, which are the same for every program,
+    // and of applications of them
+    #[test]
+    fn test_generate() {
+        let options = CompileOptions {
+            ski: true,
+            ..CompileOptions::default()
+        };
+        let output = crate::compile("let Twice f x = f (f x); let S = K", &options).unwrap();
+        let expected = indoc! {r#"
+            interface Fun { arg: unknown; ret: unknown }
+            type App<F, X> = F extends Fun ? (F & { arg: X })["ret"] : never;
+            interface S_ extends Fun { ret: S_$1<this["arg"]> }
+            interface S_$1<x> extends Fun { ret: S_$2<x, this["arg"]> }
+            interface S_$2<x, y> extends Fun { ret: App<App<x, this["arg"]>, App<y, this["arg"]>> }
+            interface K_ extends Fun { ret: K_$1<this["arg"]> }
+            interface K_$1<x> extends Fun { ret: x }
+            interface I extends Fun { ret: this["arg"] }
+            interface B extends Fun { ret: B$1<this["arg"]> }
+            interface B$1<x> extends Fun { ret: B$2<x, this["arg"]> }
+            interface B$2<x, y> extends Fun { ret: App<x, App<y, this["arg"]>> }
+            interface C extends Fun { ret: C$1<this["arg"]> }
+            interface C$1<x> extends Fun { ret: C$2<x, this["arg"]> }
+            interface C$2<x, y> extends Fun { ret: App<App<x, this["arg"]>, y> }
+            type Twice = App<App<S_, B>, I>;
+            type S = K;
+        "#};
+        assert_eq!(output.code, expected);
+    }
+
+    // the declarations of the combinators with the names given to them
+    fn definitions(combinators: &Combinators) -> Vec<Statement> {
+        let program = parse(DEFINITIONS).unwrap();
+        program
+            .into_iter()
+            .zip(combinators.names())
+            .map(|(stmt, new_name)| {
+                let Statement::Declaration {
+                    name: _,
+                    value,
+                    attrs,
+                    doc,
+                } = stmt;
+                Statement::Declaration {
+                    name: new_name,
+                    value,
+                    attrs,
+                    doc,
+                }
+            })
+            .collect()
+    }
+
+    // the program that the pipeline makes of translated declarations has the same normal forms as the one it makes
+    // of the original declarations, which are lambda-lifted by the generator, when they are applied to enough
+    // arguments, while they may differ without arguments, e.g. `fun f x -> f x` is translated into `I`
+    #[test]
+    fn test_semantics() {
+        let source = concat!(
+            include_str!("../../examples/factorial.ml"),
+            "let Two f x = f (f x);\nlet Three f x = f (f (f x));\nlet Dup x y = let z = y x in z z"
+        );
+        let program = parse(source).unwrap();
+        let pipeline = transformer::pipeline();
+        let (lifted, _) = pipeline.run(&program, &[]);
+        let (translated, combinators) = translate(&program, true);
+        let (translated, _) = pipeline.run(&translated, &[]);
+        let translated = [definitions(&combinators.unwrap()), translated].concat();
+        let terms = [
+            "fun f x -> Mul One One f x",
+            "fun f x -> Pred (Mul Two Two) f x",
+            "fun a b -> IsZero (Pred One) a b",
+            "fun f x -> Factorial Three f x",
+            "fun a b -> Dup a b",
+        ];
+        for term in terms {
+            let term = parse_term(term).unwrap();
+            let expected = transformer::evaluate(&lifted, &term).unwrap();
+            let actual = transformer::evaluate(&translated, &term).unwrap();
+            assert_eq!(actual.to_string(), expected.to_string(), "{term}");
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use serde_json::json;
+
+use super::parser::Span;
+
+// generated code is marked with the spans that its parts come from, as the parts are nested in each other
+// before their positions are known. the marks are made of characters of the private use area,
+// which generated names never contain, and which are escaped in the text of doc comments
+const MARK_START: char = '\u{E000}';
+const MARK_END: char = '\u{E001}';
+
+// a mark of the code that follows it
+pub(super) fn mark(span: Span) -> String {
+    format!("{MARK_START}{}:{}{MARK_END}", span.start, span.end)
+}
+
+// a text from the source, e.g. a doc comment, in which the characters of marks are escaped
+// so that it can be embedded in marked code
+pub(super) fn escape_marks(text: &str) -> String {
+    text.replace(MARK_START, "\\uE000")
+        .replace(MARK_END, "\\uE001")
+}
+
+/// Generated code with the spans of the source that its parts come from.
+#[derive(Debug, Default)]
+pub struct Mapped {
+    pub code: String,
+    /// The offsets in the code where the parts begin, in increasing order, with their spans.
+    pub origins: Vec<(usize, Span)>,
+}
+
+impl Mapped {
+    pub fn push_str(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    // appends marked code, whose marks are taken out and recorded as origins
+    pub(super) fn push_marked(&mut self, code: &str) {
+        let mut rest = code;
+        while let Some(start) = rest.find(MARK_START) {
+            self.code.push_str(&rest[..start]);
+            let (mark, after) = rest[start + MARK_START.len_utf8()..]
+                .split_once(MARK_END)
+                .expect("unterminated mark");
+            let (span_start, span_end) = mark.split_once(':').expect("invalid mark");
+            let span = Span {
+                start: span_start.parse().expect("invalid mark"),
+                end: span_end.parse().expect("invalid mark"),
+            };
+            self.origins.push((self.code.len(), span));
+            rest = after;
+        }
+        self.code.push_str(rest);
+    }
+}
+
+/// Finds lines and columns of offsets in a text, where columns are counted in UTF-16 code units
+/// as in source maps.
+struct Lines<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Lines<'a> {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Lines { text, starts }
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.text[self.starts[line]..offset].encode_utf16().count();
+        (line, column)
+    }
+}
+
+/// A position in the generated code and the position in the source that it comes from,
+/// which are zero-based.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub generated_line: usize,
+    pub generated_column: usize,
+    pub source_line: usize,
+    pub source_column: usize,
+}
+
+/// A source map from generated code back to a single source.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    /// The mappings in the order of the generated code.
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Maps each part of the code to the start of the span it comes from.
+    pub fn new(mapped: &Mapped, source: &str) -> SourceMap {
+        let code_lines = Lines::new(&mapped.code);
+        let source_lines = Lines::new(source);
+        let mappings = mapped
+            .origins
+            .iter()
+            .map(|&(offset, span)| {
+                let (generated_line, generated_column) = code_lines.position(offset);
+                let (source_line, source_column) = source_lines.position(span.start);
+                Mapping {
+                    generated_line,
+                    generated_column,
+                    source_line,
+                    source_column,
+                }
+            })
+            .collect();
+        SourceMap { mappings }
+    }
+
+    /// Encodes the mappings in the `mappings` field of a source map.
+    pub fn encode_mappings(&self) -> String {
+        let mut res = String::new();
+        let mut line = 0;
+        // every field but the generated column is relative to the previous segment in the whole map
+        let mut prev_column = 0;
+        let mut prev_source_line = 0;
+        let mut prev_source_column = 0;
+        for mapping in &self.mappings {
+            if mapping.generated_line > line {
+                for _ in line..mapping.generated_line {
+                    res.push(';');
+                }
+                line = mapping.generated_line;
+                prev_column = 0;
+            } else if !res.is_empty() && !res.ends_with(';') {
+                res.push(',');
+            }
+            let fields = [
+                delta(mapping.generated_column, prev_column),
+                0,
+                delta(mapping.source_line, prev_source_line),
+                delta(mapping.source_column, prev_source_column),
+            ];
+            for field in fields {
+                encode_vlq(field, &mut res);
+            }
+            prev_column = mapping.generated_column;
+            prev_source_line = mapping.source_line;
+            prev_source_column = mapping.source_column;
+        }
+        res
+    }
+
+    /// Writes the source map in the format of version 3, for the generated file `file` and the source `source`,
+    /// which are relative to the source map.
+    pub fn to_json(&self, file: &str, source: &str) -> String {
+        json!({
+            "version": 3,
+            "file": file,
+            "sources": [source],
+            "names": [],
+            "mappings": self.encode_mappings(),
+        })
+        .to_string()
+    }
+}
+
+fn delta(value: usize, prev: usize) -> i64 {
+    value as i64 - prev as i64
+}
+
+// a base64 VLQ, whose digits hold five bits each from the least significant one, and whose lowest bit is the sign
+fn encode_vlq(value: i64, res: &mut String) {
+    const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut vlq = if value < 0 {
+        (value.unsigned_abs() << 1) | 1
+    } else {
+        (value as u64) << 1
+    };
+    loop {
+        let digit = (vlq & 31) as usize;
+        vlq >>= 5;
+        let continued = if vlq > 0 { 32 } else { 0 };
+        res.push(BASE64[digit | continued] as char);
+        if vlq == 0 {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_vlq() {
+        let encode = |value| {
+            let mut res = String::new();
+            encode_vlq(value, &mut res);
+            res
+        };
+        assert_eq!(encode(0), "A");
+        assert_eq!(encode(1), "C");
+        assert_eq!(encode(-1), "D");
+        assert_eq!(encode(16), "gB");
+        assert_eq!(encode(123), "2H");
+    }
+
+    #[test]
+    fn test_source_map() {
+        let source = "let F = (* λ *) x;\nlet G = y";
+        let span_of = |text: &str| {
+            let start = source.find(text).unwrap();
+            Span {
+                start,
+                end: start + text.len(),
+            }
+        };
+        let mut mapped = Mapped::default();
+        mapped.push_str("// header\n");
+        mapped.push_marked(&format!(
+            "{}type F = x;\n{}type G = {}y;\n",
+            mark(span_of("x")),
+            mark(span_of("let G = y")),
+            mark(span_of("y")),
+        ));
+        assert_eq!(mapped.code, "// header\ntype F = x;\ntype G = y;\n");
+        let source_map = SourceMap::new(&mapped, source);
+        // columns are counted in UTF-16 code units
+        assert_eq!(
+            source_map.mappings[0],
+            Mapping {
+                generated_line: 1,
+                generated_column: 0,
+                source_line: 0,
+                source_column: 16,
+            }
+        );
+        assert_eq!(
+            source_map.mappings[2],
+            Mapping {
+                generated_line: 2,
+                generated_column: 9,
+                source_line: 1,
+                source_column: 8,
+            }
+        );
+        assert_eq!(source_map.encode_mappings(), ";AAAgB;AAChB,SAAQ");
+        assert_eq!(
+            source_map.to_json("out.ts", "in.ml"),
+            r#"{"file":"out.ts","mappings":";AAAgB;AAChB,SAAQ","names":[],"sources":["in.ml"],"version":3}"#
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Interned identifier.
+/// Symbols are compared and hashed as integers, and copying them is free.
+/// The interner is shared by all threads, so symbols can be sent between them.
+///
+/// Interned names are never freed, so the memory they take grows with the number of distinct names
+/// that a process reads, e.g. over a session of the REPL or the language server, but not with
+/// the number of times the names are read or the number of fresh symbols.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol {
+    id: u32,
+    // the number of a fresh symbol, which is 0 for interned symbols.
+    // fresh symbols share the name of their hint instead of being interned, so that making them takes no memory
+    fresh: u64,
+}
+
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+impl Default for Interner {
+    fn default() -> Interner {
+        Interner {
+            ids: HashMap::from([("", Symbol::EMPTY)]),
+            names: vec![""],
+        }
+    }
+}
+
+static INTERNER: Lazy<Mutex<Interner>> = Lazy::new(|| Mutex::new(Interner::default()));
+static FRESH: AtomicU64 = AtomicU64::new(0);
+
+// the interner, which stays consistent even if a thread panicked while holding it,
+// as a name is pushed before its id is recorded
+fn interner() -> std::sync::MutexGuard<'static, Interner> {
+    INTERNER.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl Symbol {
+    /// The empty name, which is interned in advance and can be used without accessing the interner.
+    pub const EMPTY: Symbol = Symbol { id: 0, fresh: 0 };
+
+    pub fn intern(name: &str) -> Symbol {
+        let mut interner = interner();
+        if let Some(&sym) = interner.ids.get(name) {
+            return sym;
+        }
+        // interned names live as long as the program
+        let name: &'static str = Box::leak(Box::from(name));
+        let sym = Symbol {
+            id: interner.names.len() as u32,
+            fresh: 0,
+        };
+        interner.names.push(name);
+        interner.ids.insert(name, sym);
+        sym
+    }
+
+    /// Creates a new symbol that is distinct from every other symbol, including interned ones,
+    /// but is displayed as `hint`.
+    pub fn fresh(hint: Symbol) -> Symbol {
+        let fresh = FRESH.fetch_add(1, Ordering::Relaxed) + 1;
+        Symbol { id: hint.id, fresh }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        interner().names[self.id as usize]
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// symbols are exchanged as their names
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Symbol::intern(&name))
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let x = Symbol::intern("x");
+        assert_eq!(Symbol::intern("x"), x);
+        assert_ne!(Symbol::intern("y"), x);
+        assert_eq!(x.as_str(), "x");
+    }
+
+    #[test]
+    fn test_threads() {
+        let x = std::thread::spawn(|| Symbol::intern("made_in_another_thread"))
+            .join()
+            .unwrap();
+        assert_eq!(x.as_str(), "made_in_another_thread");
+        assert_eq!(Symbol::intern("made_in_another_thread"), x);
+    }
+
+    #[test]
+    fn test_fresh() {
+        let x = Symbol::intern("x");
+        let fresh_x = Symbol::fresh(x);
+        assert_ne!(fresh_x, x);
+        assert_ne!(Symbol::fresh(x), fresh_x);
+        assert_eq!(fresh_x.as_str(), "x");
+        assert_ne!(Symbol::fresh(fresh_x), fresh_x);
+        assert_eq!(Symbol::fresh(fresh_x).as_str(), "x");
+        // fresh symbols are not interned, but share the name of their hint
+        assert_eq!(fresh_x.id, x.id);
+    }
+}
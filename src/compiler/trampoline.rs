@@ -0,0 +1,73 @@
+type Thunk<S, T> = Box<dyn FnOnce(&mut S) -> Step<S, T>>;
+type Cont<S, T> = Box<dyn FnOnce(&mut S, T) -> Step<S, T>>;
+
+/// A computation suspended at a call, so that deeply recursive functions
+/// can be run in constant stack space by [`run`].
+pub enum Step<S, T> {
+    /// The result of the computation.
+    Done(T),
+    /// Continues with another computation, whose result is the result of this one.
+    Jump(Thunk<S, T>),
+    /// Calls a computation and passes its result to the continuation.
+    Call(Thunk<S, T>, Cont<S, T>),
+}
+
+impl<S, T> Step<S, T> {
+    pub fn jump(f: impl FnOnce(&mut S) -> Step<S, T> + 'static) -> Self {
+        Step::Jump(Box::new(f))
+    }
+
+    pub fn call(
+        f: impl FnOnce(&mut S) -> Step<S, T> + 'static,
+        k: impl FnOnce(&mut S, T) -> Step<S, T> + 'static,
+    ) -> Self {
+        Step::Call(Box::new(f), Box::new(k))
+    }
+}
+
+/// Runs a computation to the end, keeping pending continuations on the heap instead of the stack.
+pub fn run<S, T>(state: &mut S, f: impl FnOnce(&mut S) -> Step<S, T>) -> T {
+    let mut conts: Vec<Cont<S, T>> = Vec::new();
+    let mut step = f(state);
+    loop {
+        step = match step {
+            Step::Done(value) => match conts.pop() {
+                Some(k) => k(state, value),
+                None => return value,
+            },
+            Step::Jump(f) => f(state),
+            Step::Call(f, k) => {
+                conts.push(k);
+                f(state)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_run {
+    use super::*;
+
+    fn sum(n: u64) -> Step<(), u64> {
+        if n == 0 {
+            Step::Done(0)
+        } else {
+            Step::call(move |_| sum(n - 1), move |_, s| Step::Done(s + n))
+        }
+    }
+
+    fn count(state: &mut u64, n: u64) -> Step<u64, u64> {
+        if n == 0 {
+            Step::Done(*state)
+        } else {
+            *state += 1;
+            Step::jump(move |state| count(state, n - 1))
+        }
+    }
+
+    #[test]
+    fn test_deep() {
+        assert_eq!(run(&mut (), |_| sum(1_000_000)), 500_000_500_000);
+        assert_eq!(run(&mut 0, |state| count(state, 1_000_000)), 1_000_000);
+    }
+}
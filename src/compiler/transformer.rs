@@ -1,178 +1,154 @@
-use std::{collections::HashSet, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
-use super::lambda::{Statement, Term};
+use super::{
+    core::{Arena, Core, Node},
+    lambda::{Statement, Term, EVAL_ATTR},
+    pipeline::{self, Invariant, Pipeline},
+    symbol::Symbol,
+    trampoline::{self, Step},
+};
 
-fn find_fresh_var(env: &HashSet<String>, prefix: &String) -> String {
-    let mut var = prefix.clone();
-    let mut i = 0;
-    loop {
-        if !env.contains(&var) {
-            return var;
-        }
-        var = format!("{prefix}{i}");
-        i += 1;
-    }
+// passes are written as trampolined functions, so that they can transform arbitrarily deep terms
+type Rewrite = fn(&mut Arena, Node) -> Step<Arena, Node>;
+
+fn is_normal_app_part(arena: &Arena, term: Node) -> bool {
+    !arena.contains_abs(term) && !arena.contains_let(term)
 }
 
-#[cfg(test)]
-mod tests_find_fresh_var {
-    use super::*;
+// opens the body of a binder with a fresh variable
+fn open_fresh(arena: &mut Arena, hint: Symbol, body: Node) -> (Symbol, Node) {
+    let var = Symbol::fresh(hint);
+    let fvar = arena.fvar(var);
+    (var, arena.open(body, fvar))
+}
 
-    #[test]
-    fn test() {
-        let env = HashSet::from([String::from("x"), String::from("y"), String::from("y0")]);
-        assert_eq!(find_fresh_var(&env, &String::from("x")), String::from("x0"));
-        assert_eq!(find_fresh_var(&env, &String::from("y")), String::from("y1"));
-        assert_eq!(find_fresh_var(&env, &String::from("z")), String::from("z"));
-    }
+// runs f on a body opened with var, and passes the result closed again to k
+fn then_close(
+    f: impl FnOnce(&mut Arena) -> Step<Arena, Node> + 'static,
+    var: Symbol,
+    k: impl FnOnce(&mut Arena, Node) -> Step<Arena, Node> + 'static,
+) -> Step<Arena, Node> {
+    Step::call(f, move |arena, body| {
+        let body = arena.close(body, var);
+        k(arena, body)
+    })
 }
 
-fn is_normal_app_part(term: &Term) -> bool {
-    match term {
-        Term::Var { name: _ } => true,
-        Term::App { func, arg } => is_normal_app_part(func) && is_normal_app_part(arg),
-        Term::Abs { param: _, body: _ } => false,
-        Term::Let {
-            name: _,
-            value: _,
-            body: _,
-        } => false,
-    }
+// applies f to the body of a binder after opening it with a fresh variable
+fn under_binder(
+    arena: &mut Arena,
+    hint: Symbol,
+    body: Node,
+    f: Rewrite,
+    k: impl FnOnce(&mut Arena, Node) -> Step<Arena, Node> + 'static,
+) -> Step<Arena, Node> {
+    let (var, opened) = open_fresh(arena, hint, body);
+    then_close(move |arena| f(arena, opened), var, k)
 }
 
-fn normalize_app(term: &Term) -> Term {
-    match term {
-        Term::Var { name: _ } => term.clone(),
-        Term::App { func, arg } => {
-            if !is_normal_app_part(func) {
-                match func.as_ref() {
-                    Term::Var { name: _ } => normalize_app(&Term::App {
-                        func: Rc::new(normalize_app(func)),
-                        arg: Rc::clone(arg),
-                    }),
-                    Term::App { func: _, arg: _ } => normalize_app(&Term::App {
-                        func: Rc::new(normalize_app(func)),
-                        arg: Rc::clone(arg),
-                    }),
-                    Term::Abs { param, body } => {
-                        let arg_fvs = arg.free_vars();
-                        let new_name = find_fresh_var(&arg_fvs, &String::from("v"));
-                        Term::Let {
-                            name: new_name.clone(),
-                            value: Rc::new(Term::Abs {
-                                param: param.clone(),
-                                body: Rc::new(normalize_app(body)),
-                            }),
-                            body: Rc::new(normalize_app(&Term::App {
-                                func: Rc::new(Term::Var {
-                                    name: new_name.clone(),
-                                }),
-                                arg: Rc::clone(arg),
-                            })),
-                        }
+fn normalize_app(arena: &mut Arena, term: Node) -> Step<Arena, Node> {
+    match *arena.get(term) {
+        Core::BVar { index: _ } => Step::Done(term),
+        Core::FVar { name: _ } => Step::Done(term),
+        Core::App { func, arg } => {
+            if !is_normal_app_part(arena, func) {
+                match *arena.get(func) {
+                    Core::BVar { index: _ }
+                    | Core::FVar { name: _ }
+                    | Core::App { func: _, arg: _ } => Step::call(
+                        move |arena| normalize_app(arena, func),
+                        move |arena, func| {
+                            let term = arena.add_from(term, Core::App { func, arg });
+                            normalize_app(arena, term)
+                        },
+                    ),
+                    Core::Abs { hint: _, body: _ } => {
+                        let hint = Symbol::from("v");
+                        let var = Symbol::fresh(hint);
+                        Step::call(
+                            move |arena| normalize_app(arena, func),
+                            move |arena, value| {
+                                let fvar = arena.fvar(var);
+                                let body = arena.add_from(term, Core::App { func: fvar, arg });
+                                then_close(
+                                    move |arena| normalize_app(arena, body),
+                                    var,
+                                    move |arena, body| Step::Done(arena.let_(hint, value, body)),
+                                )
+                            },
+                        )
                     }
-                    Term::Let { name, value, body } => {
-                        let arg_fvs = arg.free_vars();
-                        if arg_fvs.contains(name) {
-                            let new_name = find_fresh_var(&arg_fvs, name);
-                            Term::Let {
-                                name: new_name.clone(),
-                                value: Rc::new(normalize_app(value)),
-                                body: Rc::new(normalize_app(&Term::App {
-                                    func: Rc::new(body.subst(
-                                        name,
-                                        &Term::Var {
-                                            name: new_name.clone(),
-                                        },
-                                    )),
-                                    arg: Rc::clone(arg),
-                                })),
-                            }
-                        } else {
-                            Term::Let {
-                                name: name.clone(),
-                                value: Rc::new(normalize_app(value)),
-                                body: Rc::new(normalize_app(&Term::App {
-                                    func: Rc::clone(body),
-                                    arg: Rc::clone(arg),
-                                })),
-                            }
-                        }
+                    Core::Let { hint, value, body } => {
+                        // arg is locally closed, so it can be moved under the binder as is
+                        let body = arena.add_from(term, Core::App { func: body, arg });
+                        let term = arena.let_(hint, value, body);
+                        normalize_app(arena, term)
                     }
                 }
-            } else if !is_normal_app_part(arg) {
-                match arg.as_ref() {
-                    Term::Var { name: _ } => normalize_app(&Term::App {
-                        func: Rc::clone(func),
-                        arg: Rc::new(normalize_app(arg)),
-                    }),
-                    Term::App { func: _, arg: _ } => normalize_app(&Term::App {
-                        func: Rc::clone(func),
-                        arg: Rc::new(normalize_app(arg)),
-                    }),
-                    Term::Abs { param, body } => {
-                        let func_fvs = func.free_vars();
-                        let new_name = find_fresh_var(&func_fvs, &String::from("v"));
-                        Term::Let {
-                            name: new_name.clone(),
-                            value: Rc::new(Term::Abs {
-                                param: param.clone(),
-                                body: Rc::new(normalize_app(body)),
-                            }),
+            } else if !is_normal_app_part(arena, arg) {
+                match *arena.get(arg) {
+                    Core::BVar { index: _ }
+                    | Core::FVar { name: _ }
+                    | Core::App { func: _, arg: _ } => Step::call(
+                        move |arena| normalize_app(arena, arg),
+                        move |arena, arg| {
+                            let term = arena.add_from(term, Core::App { func, arg });
+                            normalize_app(arena, term)
+                        },
+                    ),
+                    Core::Abs { hint: _, body: _ } => Step::call(
+                        move |arena| normalize_app(arena, arg),
+                        move |arena, value| {
                             // body is already normalized
-                            body: Rc::new(Term::App {
-                                func: Rc::clone(func),
-                                arg: Rc::new(Term::Var {
-                                    name: new_name.clone(),
-                                }),
-                            }),
-                        }
-                    }
-                    Term::Let { name, value, body } => {
-                        let func_fvs = func.free_vars();
-                        if func_fvs.contains(name) {
-                            let new_name = find_fresh_var(&func_fvs, name);
-                            Term::Let {
-                                name: new_name.clone(),
-                                value: Rc::new(normalize_app(value)),
-                                body: Rc::new(normalize_app(&Term::App {
-                                    func: Rc::clone(func),
-                                    arg: Rc::new(body.subst(
-                                        name,
-                                        &Term::Var {
-                                            name: new_name.clone(),
-                                        },
-                                    )),
-                                })),
-                            }
-                        } else {
-                            Term::Let {
-                                name: name.clone(),
-                                value: Rc::new(normalize_app(value)),
-                                body: Rc::new(normalize_app(&Term::App {
-                                    func: Rc::clone(func),
-                                    arg: Rc::clone(body),
-                                })),
-                            }
-                        }
+                            let var = arena.bvar(0);
+                            let body = arena.add_from(term, Core::App { func, arg: var });
+                            Step::Done(arena.let_("v", value, body))
+                        },
+                    ),
+                    Core::Let { hint, value, body } => {
+                        // func is locally closed, so it can be moved under the binder as is
+                        let body = arena.add_from(term, Core::App { func, arg: body });
+                        let term = arena.let_(hint, value, body);
+                        normalize_app(arena, term)
                     }
                 }
             } else {
-                term.clone()
+                Step::Done(term)
             }
         }
-        Term::Abs { param, body } => Term::Abs {
-            param: param.clone(),
-            body: Rc::new(normalize_app(body)),
-        },
-        Term::Let { name, value, body } => Term::Let {
-            name: name.clone(),
-            value: Rc::new(normalize_app(value)),
-            body: Rc::new(normalize_app(body)),
-        },
+        // bodies without abstractions and lets are already normal, and need not be opened
+        Core::Abs { hint: _, body } if is_normal_app_part(arena, body) => Step::Done(term),
+        Core::Abs { hint, body } => {
+            under_binder(arena, hint, body, normalize_app, move |arena, body| {
+                Step::Done(arena.add_from(term, Core::Abs { hint, body }))
+            })
+        }
+        Core::Let { hint, value, body } => Step::call(
+            move |arena| normalize_app(arena, value),
+            move |arena, value| {
+                if is_normal_app_part(arena, body) {
+                    Step::Done(arena.add_from(term, Core::Let { hint, value, body }))
+                } else {
+                    under_binder(arena, hint, body, normalize_app, move |arena, body| {
+                        Step::Done(arena.add_from(term, Core::Let { hint, value, body }))
+                    })
+                }
+            },
+        ),
     }
 }
 
+#[cfg(test)]
+fn run(pass: Rewrite, term: &Term) -> Term {
+    let mut arena = Arena::new();
+    let node = arena.add_term(term);
+    let node = trampoline::run(&mut arena, |arena| pass(arena, node));
+    arena.to_term(node)
+}
+
 #[cfg(test)]
 mod tests_normalize_app {
     use super::*;
@@ -182,276 +158,282 @@ mod tests_normalize_app {
         let term = Term::App {
             func: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
             arg: Rc::new(Term::Var {
-                name: String::from("z"),
+                name: Symbol::from("z"),
             }),
         };
-        assert_eq!(normalize_app(&term), term);
+        assert_eq!(run(normalize_app, &term), term);
     }
 
     #[test]
     fn test_app_abs() {
         let term = Term::App {
             func: Rc::new(Term::Abs {
-                param: String::from("x"),
+                param: Symbol::from("x"),
                 body: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                 }),
             }),
             arg: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
         };
         let expected = Term::Let {
-            name: String::from("v"),
+            name: Symbol::from("v"),
             value: Rc::new(Term::Abs {
-                param: String::from("x"),
+                param: Symbol::from("x"),
                 body: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                 }),
             }),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("v"),
+                    name: Symbol::from("v"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
         };
-        assert_eq!(normalize_app(&term), expected);
+        assert_eq!(run(normalize_app, &term), expected);
 
         let term = Term::App {
             func: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
             arg: Rc::new(Term::Abs {
-                param: String::from("x"),
+                param: Symbol::from("x"),
                 body: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                 }),
             }),
         };
         let expected = Term::Let {
-            name: String::from("v"),
+            name: Symbol::from("v"),
             value: Rc::new(Term::Abs {
-                param: String::from("x"),
+                param: Symbol::from("x"),
                 body: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                 }),
             }),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("v"),
+                    name: Symbol::from("v"),
                 }),
             }),
         };
-        assert_eq!(normalize_app(&term), expected);
+        assert_eq!(run(normalize_app, &term), expected);
     }
 
     #[test]
     fn test_app_let() {
         let term = Term::App {
             func: Rc::new(Term::Let {
-                name: String::from("x"),
+                name: Symbol::from("x"),
                 value: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
             arg: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
         };
         let expected = Term::Let {
-            name: String::from("x0"),
+            name: Symbol::from("x0"),
             value: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
             }),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x0"),
+                    name: Symbol::from("x0"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
         };
-        assert_eq!(normalize_app(&term), expected);
+        assert_eq!(run(normalize_app, &term), expected);
 
         let term = Term::App {
             func: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
             arg: Rc::new(Term::Let {
-                name: String::from("x"),
+                name: Symbol::from("x"),
                 value: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
         };
         let expected = Term::Let {
-            name: String::from("x0"),
+            name: Symbol::from("x0"),
             value: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
             }),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("x0"),
+                    name: Symbol::from("x0"),
                 }),
             }),
         };
-        assert_eq!(normalize_app(&term), expected);
+        assert_eq!(run(normalize_app, &term), expected);
     }
 }
 
-fn is_normal_abs_body(term: &Term) -> bool {
-    match term {
-        Term::Var { name: _ } => true,
-        Term::App { func, arg } => is_normal_abs_body(func) && is_normal_abs_body(arg),
-        Term::Abs { param: _, body } => is_normal_abs_body(body),
-        Term::Let {
-            name: _,
-            value: _,
-            body: _,
-        } => false,
-    }
+fn is_normal_abs_body(arena: &Arena, term: Node) -> bool {
+    !arena.contains_let(term)
 }
 
-fn normalize_abs(term: &Term) -> Term {
-    match term {
-        Term::Var { name: _ } => term.clone(),
+fn normalize_abs(arena: &mut Arena, term: Node) -> Step<Arena, Node> {
+    match *arena.get(term) {
+        Core::BVar { index: _ } => Step::Done(term),
+        Core::FVar { name: _ } => Step::Done(term),
         // assuming term is already normalized by normalize_app
-        Term::App { func: _, arg: _ } => term.clone(),
-        Term::Abs { param, body } => {
-            match body.as_ref() {
-                Term::Var { name: _ } => term.clone(),
-                // assuming body is normalized by normalize_app
-                Term::App { func: _, arg: _ } => term.clone(),
-                Term::Abs { param: _, body: _ } => {
-                    if is_normal_abs_body(body) {
-                        term.clone()
-                    } else {
-                        normalize_abs(&Term::Abs {
-                            param: param.clone(),
-                            body: Rc::new(normalize_abs(body)),
-                        })
-                    }
-                }
-                Term::Let {
-                    name: let_name,
-                    value: let_value,
-                    body: let_body,
-                } => {
-                    let let_value_fvs = let_value.free_vars();
-                    let new_let_name = if let_name == param {
-                        let let_body_fvs = let_body.free_vars();
-                        find_fresh_var(&let_body_fvs, let_name)
-                    } else {
-                        let_name.clone()
-                    };
-                    if let_value_fvs.contains(param) {
-                        Term::Let {
-                            name: new_let_name.clone(),
-                            value: Rc::new(normalize_abs(&Term::Abs {
-                                param: param.clone(),
-                                body: Rc::clone(let_value),
-                            })),
-                            body: Rc::new(normalize_abs(&Term::Abs {
-                                param: param.clone(),
-                                body: Rc::new(let_body.subst(
-                                    let_name,
-                                    &Term::App {
-                                        func: Rc::new(Term::Var {
-                                            name: new_let_name.clone(),
-                                        }),
-                                        arg: Rc::new(Term::Var {
-                                            name: param.clone(),
-                                        }),
-                                    },
-                                )),
-                            })),
-                        }
-                    } else {
-                        Term::Let {
-                            name: new_let_name.clone(),
-                            value: Rc::new(normalize_abs(let_value)),
-                            body: Rc::new(normalize_abs(&&Term::Abs {
-                                param: param.clone(),
-                                body: Rc::new(let_body.subst(
-                                    let_name,
-                                    &Term::Var {
-                                        name: new_let_name.clone(),
-                                    },
-                                )),
-                            })),
-                        }
-                    }
-                }
+        Core::App { func: _, arg: _ } => Step::Done(term),
+        Core::Abs { hint, body } => {
+            if is_normal_abs_body(arena, body) {
+                Step::Done(term)
+            } else {
+                let (param, body) = open_fresh(arena, hint, body);
+                normalize_abs_body(arena, term, hint, param, body)
             }
         }
-        Term::Let { name, value, body } => Term::Let {
-            name: name.clone(),
-            value: Rc::new(normalize_abs(value)),
-            body: Rc::new(normalize_abs(body)),
-        },
+        Core::Let { hint, value, body } => Step::call(
+            move |arena| normalize_abs(arena, value),
+            move |arena, value| {
+                under_binder(arena, hint, body, normalize_abs, move |arena, body| {
+                    Step::Done(arena.add_from(term, Core::Let { hint, value, body }))
+                })
+            },
+        ),
+    }
+}
+
+// normalizes `fun param -> body`, which is `term`, where the body is already opened with the parameter,
+// so that a chain of lets can be floated out without closing and reopening the rest of the body
+fn normalize_abs_body(
+    arena: &mut Arena,
+    term: Node,
+    hint: Symbol,
+    param: Symbol,
+    body: Node,
+) -> Step<Arena, Node> {
+    match *arena.get(body) {
+        Core::BVar { index: _ } | Core::FVar { name: _ } => {
+            let body = arena.close(body, param);
+            Step::Done(arena.add_from(term, Core::Abs { hint, body }))
+        }
+        // assuming body is normalized by normalize_app
+        Core::App { func: _, arg: _ } => {
+            let body = arena.close(body, param);
+            Step::Done(arena.add_from(term, Core::Abs { hint, body }))
+        }
+        Core::Abs { hint: _, body: _ } => {
+            if is_normal_abs_body(arena, body) {
+                let body = arena.close(body, param);
+                Step::Done(arena.add_from(term, Core::Abs { hint, body }))
+            } else {
+                Step::call(
+                    move |arena| normalize_abs(arena, body),
+                    move |arena, body| normalize_abs_body(arena, term, hint, param, body),
+                )
+            }
+        }
+        Core::Let {
+            hint: let_hint,
+            value: let_value,
+            body: let_body,
+        } => {
+            let let_var = Symbol::fresh(let_hint);
+            let let_fvar = arena.fvar(let_var);
+            let (value, let_body) = if arena.has_free_var(let_value, param) {
+                // lift the value out of the abstraction by abstracting it over the parameter
+                let value = arena.close(let_value, param);
+                let value = arena.abs(hint, value);
+                let param_fvar = arena.fvar(param);
+                let lifted = arena.app(let_fvar, param_fvar);
+                (value, arena.open(let_body, lifted))
+            } else {
+                (let_value, arena.open(let_body, let_fvar))
+            };
+            // the floated let comes from the let in the body
+            let let_term = body;
+            Step::call(
+                move |arena| normalize_abs(arena, value),
+                move |_, value| {
+                    then_close(
+                        move |arena| normalize_abs_body(arena, term, hint, param, let_body),
+                        let_var,
+                        move |arena, body| {
+                            let core = Core::Let {
+                                hint: let_hint,
+                                value,
+                                body,
+                            };
+                            Step::Done(arena.add_from(let_term, core))
+                        },
+                    )
+                },
+            )
+        }
     }
 }
 
@@ -462,183 +444,161 @@ mod tests_normalize_abs {
     #[test]
     fn test_abs_normal() {
         let term = Term::Abs {
-            param: String::from("x"),
+            param: Symbol::from("x"),
             body: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
-        assert_eq!(normalize_abs(&term), term);
+        assert_eq!(run(normalize_abs, &term), term);
     }
 
     #[test]
     fn test_abs_let() {
         let term = Term::Abs {
-            param: String::from("x"),
+            param: Symbol::from("x"),
             body: Rc::new(Term::Let {
-                name: String::from("y"),
+                name: Symbol::from("y"),
                 value: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
         let expected = Term::Let {
-            name: String::from("y"),
+            name: Symbol::from("y"),
             value: Rc::new(Term::Abs {
-                param: String::from("x"),
+                param: Symbol::from("x"),
                 body: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
             body: Rc::new(Term::Abs {
-                param: String::from("x"),
+                param: Symbol::from("x"),
                 body: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("x"),
+                        name: Symbol::from("x"),
                     }),
                 }),
             }),
         };
-        assert_eq!(normalize_abs(&term), expected);
+        assert_eq!(run(normalize_abs, &term), expected);
 
         let term = Term::Abs {
-            param: String::from("x"),
+            param: Symbol::from("x"),
             body: Rc::new(Term::Let {
-                name: String::from("y"),
+                name: Symbol::from("y"),
                 value: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
         let expected = Term::Let {
-            name: String::from("y"),
+            name: Symbol::from("y"),
             value: Rc::new(Term::Var {
-                name: String::from("z"),
+                name: Symbol::from("z"),
             }),
             body: Rc::new(Term::Abs {
-                param: String::from("x"),
+                param: Symbol::from("x"),
                 body: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
         };
-        assert_eq!(normalize_abs(&term), expected);
+        assert_eq!(run(normalize_abs, &term), expected);
 
         let term = Term::Abs {
-            param: String::from("x"),
+            param: Symbol::from("x"),
             body: Rc::new(Term::Let {
-                name: String::from("x"),
+                name: Symbol::from("x"),
                 value: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
         };
         let expected = Term::Let {
-            name: String::from("x0"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::Var {
-                name: String::from("y"),
+                name: Symbol::from("y"),
             }),
             body: Rc::new(Term::Abs {
-                param: String::from("x"),
+                param: Symbol::from("x0"),
                 body: Rc::new(Term::Var {
-                    name: String::from("x0"),
+                    name: Symbol::from("x"),
                 }),
             }),
         };
-        assert_eq!(normalize_abs(&term), expected);
-    }
-}
-
-fn is_normal_let_value(term: &Term) -> bool {
-    match term {
-        Term::Var { name: _ } => true,
-        Term::App { func, arg } => is_normal_let_value(func) && is_normal_let_value(arg),
-        Term::Abs { param: _, body } => is_normal_let_value(body),
-        Term::Let {
-            name: _,
-            value: _,
-            body: _,
-        } => false,
+        assert_eq!(run(normalize_abs, &term), expected);
     }
 }
 
-fn normalize_let(term: &Term) -> Term {
-    match term {
-        Term::Var { name: _ } => term.clone(),
+fn normalize_let(arena: &mut Arena, term: Node) -> Step<Arena, Node> {
+    match *arena.get(term) {
+        Core::BVar { index: _ } => Step::Done(term),
+        Core::FVar { name: _ } => Step::Done(term),
         // assuming term is already normalized by normalize_app
-        Term::App { func: _, arg: _ } => term.clone(),
+        Core::App { func: _, arg: _ } => Step::Done(term),
         // assuming term is already normalized by normalize_abs
-        Term::Abs { param: _, body: _ } => term.clone(),
-        Term::Let { name, value, body } => {
-            match value.as_ref() {
-                Term::Var { name: _ } => Term::Let {
-                    name: name.clone(),
-                    value: Rc::clone(value),
-                    body: Rc::new(normalize_let(body)),
-                },
-                // assuming value is already normalized by normalize_app
-                Term::App { func: _, arg: _ } => Term::Let {
-                    name: name.clone(),
-                    value: Rc::clone(value),
-                    body: Rc::new(normalize_let(body)),
-                },
-                // assuming value is already normalized by normalize_abs
-                Term::Abs { param: _, body: _ } => Term::Let {
-                    name: name.clone(),
-                    value: Rc::clone(value),
-                    body: Rc::new(normalize_let(body)),
-                },
-                Term::Let {
-                    name: inner_name,
+        Core::Abs { hint: _, body: _ } => Step::Done(term),
+        Core::Let { hint, value, body } => match *arena.get(value) {
+            Core::BVar { index: _ } | Core::FVar { name: _ } => {
+                under_binder(arena, hint, body, normalize_let, move |arena, body| {
+                    Step::Done(arena.add_from(term, Core::Let { hint, value, body }))
+                })
+            }
+            // assuming value is already normalized by normalize_app
+            Core::App { func: _, arg: _ } => {
+                under_binder(arena, hint, body, normalize_let, move |arena, body| {
+                    Step::Done(arena.add_from(term, Core::Let { hint, value, body }))
+                })
+            }
+            // assuming value is already normalized by normalize_abs
+            Core::Abs { hint: _, body: _ } => {
+                under_binder(arena, hint, body, normalize_let, move |arena, body| {
+                    Step::Done(arena.add_from(term, Core::Let { hint, value, body }))
+                })
+            }
+            Core::Let {
+                hint: inner_hint,
+                value: inner_value,
+                body: inner_body,
+            } => {
+                // float the inner binder out, and normalize the result again as its value may be a let too.
+                // body does not refer to the inner binder, so no renaming is needed
+                let (inner_var, inner_body) = open_fresh(arena, inner_hint, inner_body);
+                let body = arena.add_from(
+                    term,
+                    Core::Let {
+                        hint,
+                        value: inner_body,
+                        body,
+                    },
+                );
+                let body = arena.close(body, inner_var);
+                let core = Core::Let {
+                    hint: inner_hint,
                     value: inner_value,
-                    body: inner_body,
-                } => {
-                    if is_normal_let_value(&inner_value) {
-                        let body_fvs = body.free_vars();
-                        let new_name = if inner_name == name || body_fvs.contains(inner_name) {
-                            let inner_body_fvs = inner_body.free_vars();
-                            let env = body_fvs.union(&inner_body_fvs).cloned().collect();
-                            find_fresh_var(&env, inner_name)
-                        } else {
-                            inner_name.clone()
-                        };
-                        Term::Let {
-                            name: new_name.clone(),
-                            value: Rc::clone(inner_value),
-                            body: Rc::new(normalize_let(&Term::Let {
-                                name: name.clone(),
-                                value: Rc::new(
-                                    inner_body.subst(inner_name, &Term::Var { name: new_name }),
-                                ),
-                                body: Rc::clone(body),
-                            })),
-                        }
-                    } else {
-                        normalize_let(&Term::Let {
-                            name: name.clone(),
-                            value: Rc::new(normalize_let(value)),
-                            body: Rc::clone(body),
-                        })
-                    }
-                }
+                    body,
+                };
+                let term = arena.add_from(value, core);
+                Step::jump(move |arena| normalize_let(arena, term))
             }
-        }
+        },
     }
 }
 
@@ -649,196 +609,530 @@ mod tests_normalize_let {
     #[test]
     fn test_let_normal() {
         let term = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
             }),
             body: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
         };
-        assert_eq!(normalize_let(&term), term);
+        assert_eq!(run(normalize_let, &term), term);
     }
 
     #[test]
     fn test_let_let() {
         let term = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::Let {
-                name: String::from("y"),
+                name: Symbol::from("y"),
                 value: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("w"),
+                        name: Symbol::from("w"),
                     }),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
             }),
             body: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
         };
         let expected = Term::Let {
-            name: String::from("y"),
+            name: Symbol::from("y"),
             value: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("w"),
+                    name: Symbol::from("w"),
                 }),
             }),
             body: Rc::new(Term::Let {
-                name: String::from("x"),
+                name: Symbol::from("x"),
                 value: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
         };
-        assert_eq!(normalize_let(&term), expected);
+        assert_eq!(run(normalize_let, &term), expected);
 
         let term = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::Let {
-                name: String::from("x"),
+                name: Symbol::from("x"),
                 value: Rc::new(Term::App {
                     func: Rc::new(Term::Var {
-                        name: String::from("y"),
+                        name: Symbol::from("y"),
                     }),
                     arg: Rc::new(Term::Var {
-                        name: String::from("z"),
+                        name: Symbol::from("z"),
                     }),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
             body: Rc::new(Term::Var {
-                name: String::from("x"),
+                name: Symbol::from("x"),
             }),
         };
         let expected = Term::Let {
-            name: String::from("x0"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::App {
                 func: Rc::new(Term::Var {
-                    name: String::from("y"),
+                    name: Symbol::from("y"),
                 }),
                 arg: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
             }),
             body: Rc::new(Term::Let {
-                name: String::from("x"),
+                name: Symbol::from("x0"),
                 value: Rc::new(Term::Var {
-                    name: String::from("x0"),
+                    name: Symbol::from("x"),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x0"),
                 }),
             }),
         };
-        assert_eq!(normalize_let(&term), expected);
+        assert_eq!(run(normalize_let, &term), expected);
     }
 }
 
-fn mangle(term: &Term, env: &HashSet<String>) -> Term {
-    match term {
-        Term::Var { name: _ } => term.clone(),
-        Term::App { func: _, arg: _ } => term.clone(),
-        Term::Abs { param: _, body: _ } => term.clone(),
-        Term::Let { name, value, body } => {
-            let new_name = if env.contains(name) {
-                find_fresh_var(env, name)
+const FOLD_MAX_STEPS: usize = 10000;
+const FOLD_MAX_SIZE: usize = 10000;
+
+// values of the declarations seen so far, expanded into closed terms on demand
+#[derive(Default)]
+struct FoldEnv {
+    values: HashMap<Symbol, Rc<Term>>,
+    expanded: HashMap<Symbol, Rc<Term>>,
+}
+
+impl FoldEnv {
+    fn define(&mut self, name: Symbol, value: Rc<Term>) {
+        if self.values.insert(name, value).is_some() {
+            // a redefinition may invalidate any expansion
+            self.expanded.clear();
+        }
+    }
+
+    fn expand_var(&mut self, name: Symbol, visiting: &mut HashSet<Symbol>) -> Option<Rc<Term>> {
+        if let Some(expanded) = self.expanded.get(&name) {
+            return Some(Rc::clone(expanded));
+        }
+        let value = Rc::clone(self.values.get(&name)?);
+        if !visiting.insert(name) {
+            return None;
+        }
+        let expanded = self.expand(&value, visiting);
+        visiting.remove(&name);
+        let expanded = Rc::new(expanded?);
+        self.expanded.insert(name, Rc::clone(&expanded));
+        Some(expanded)
+    }
+
+    // expands references to earlier declarations, or returns None if the result would not be closed
+    fn expand(&mut self, term: &Term, visiting: &mut HashSet<Symbol>) -> Option<Term> {
+        let mut expanded = term.clone();
+        for fv in term.free_vars() {
+            let fv_value = self.expand_var(fv, visiting)?;
+            expanded = expanded.subst(fv, &fv_value);
+        }
+        Some(expanded)
+    }
+}
+
+fn fold_statement(stmt: &Statement, env: &mut FoldEnv, fold_all: bool) -> Statement {
+    match stmt {
+        Statement::Declaration {
+            name,
+            value,
+            attrs,
+            doc,
+        } => {
+            let fold = fold_all || attrs.iter().any(|attr| attr == EVAL_ATTR);
+            let normal = if fold {
+                env.expand(value, &mut HashSet::new())
+                    .and_then(|expanded| expanded.normalize(FOLD_MAX_STEPS, FOLD_MAX_SIZE))
             } else {
-                name.clone()
+                None
             };
-            let mut new_env = env.clone();
-            new_env.insert(name.clone());
-            Term::Let {
-                name: new_name.clone(),
-                value: Rc::clone(value),
-                body: Rc::new(mangle(
-                    &body.subst(
-                        name,
-                        &Term::Var {
-                            name: new_name.clone(),
-                        },
-                    ),
-                    &new_env,
-                )),
+            match normal {
+                Some(normal) => {
+                    let normal = Rc::new(normal);
+                    env.define(*name, Rc::clone(&normal));
+                    Statement::Declaration {
+                        name: *name,
+                        value: normal,
+                        attrs: attrs.clone(),
+                        doc: doc.clone(),
+                    }
+                }
+                None => {
+                    env.define(*name, Rc::clone(value));
+                    stmt.clone()
+                }
             }
         }
     }
 }
 
+/// Evaluates a term to its normal form, where it may refer to the declarations of a program.
+/// Returns `None` if the term refers to undefined or recursive declarations, or has no normal form within the limits.
+pub fn evaluate(program: &[Statement], term: &Term) -> Option<Term> {
+    let mut env = FoldEnv::default();
+    for stmt in program {
+        match stmt {
+            Statement::Declaration {
+                name,
+                value,
+                attrs: _,
+                doc: _,
+            } => env.define(*name, Rc::clone(value)),
+        }
+    }
+    let normal = env
+        .expand(term, &mut HashSet::new())?
+        .normalize(FOLD_MAX_STEPS, FOLD_MAX_SIZE)?;
+    // binders renamed to avoid capture are displayed with their original names,
+    // so names are reconstructed to tell them apart
+    let mut arena = Arena::new();
+    let node = arena.add_term(&normal);
+    Some(arena.to_term(node))
+}
+
+#[cfg(test)]
+mod tests_evaluate {
+    use super::*;
+    use crate::compiler::parser::{parse, parse_term};
+
+    #[test]
+    fn test_evaluate() {
+        let program = parse(
+            "let Id x = x; let Two f x = f (f x); let Omega = (fun x -> x x) (fun x -> x x);",
+        )
+        .unwrap();
+        let normal = evaluate(&program, &parse_term("Two Two").unwrap()).unwrap();
+        assert_eq!(normal.to_string(), "fun x x0 -> x (x (x (x x0)))");
+        let normal = evaluate(&program, &parse_term("Id y").unwrap());
+        assert!(normal.is_none());
+        let normal = evaluate(&program, &parse_term("Omega").unwrap());
+        assert!(normal.is_none());
+    }
+}
+
+/// Replaces closed declarations by their normal forms.
+/// Only declarations marked with `[@eval]` are folded unless `fold_all` is set.
+/// Declarations that do not reach a normal form within the step budget are left unchanged.
+pub fn fold_constants(program: &[Statement], fold_all: bool) -> Vec<Statement> {
+    let mut env = FoldEnv::default();
+    program
+        .iter()
+        .map(|stmt| fold_statement(stmt, &mut env, fold_all))
+        .collect()
+}
+
 #[cfg(test)]
-mod tests_mangle {
+mod tests_fold_constants {
     use super::*;
 
     #[test]
-    fn test_mangle() {
+    fn test_fold_constants() {
+        // let Id x = x;
+        // let[@eval] foo = Id Id;
+        // let bar = Id Id;
+        // let baz = Id y;
+        let id = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::Var {
+                name: Symbol::from("x"),
+            }),
+        };
+        let id_id = Term::App {
+            func: Rc::new(Term::Var {
+                name: Symbol::from("Id"),
+            }),
+            arg: Rc::new(Term::Var {
+                name: Symbol::from("Id"),
+            }),
+        };
+        let id_y = Term::App {
+            func: Rc::new(Term::Var {
+                name: Symbol::from("Id"),
+            }),
+            arg: Rc::new(Term::Var {
+                name: Symbol::from("y"),
+            }),
+        };
+        let program = vec![
+            Statement::Declaration {
+                name: Symbol::from("Id"),
+                value: Rc::new(id.clone()),
+                attrs: vec![],
+                doc: None,
+            },
+            Statement::Declaration {
+                name: Symbol::from("foo"),
+                value: Rc::new(id_id.clone()),
+                attrs: vec![String::from("eval")],
+                doc: None,
+            },
+            Statement::Declaration {
+                name: Symbol::from("bar"),
+                value: Rc::new(id_id.clone()),
+                attrs: vec![],
+                doc: None,
+            },
+            Statement::Declaration {
+                name: Symbol::from("baz"),
+                value: Rc::new(id_y.clone()),
+                attrs: vec![],
+                doc: None,
+            },
+        ];
+
+        let expected = vec![
+            program[0].clone(),
+            Statement::Declaration {
+                name: Symbol::from("foo"),
+                value: Rc::new(id.clone()),
+                attrs: vec![String::from("eval")],
+                doc: None,
+            },
+            program[2].clone(),
+            program[3].clone(),
+        ];
+        assert_eq!(fold_constants(&program, false), expected);
+
+        let expected = vec![
+            program[0].clone(),
+            Statement::Declaration {
+                name: Symbol::from("foo"),
+                value: Rc::new(id.clone()),
+                attrs: vec![String::from("eval")],
+                doc: None,
+            },
+            Statement::Declaration {
+                name: Symbol::from("bar"),
+                value: Rc::new(id.clone()),
+                attrs: vec![],
+                doc: None,
+            },
+            program[3].clone(),
+        ];
+        assert_eq!(fold_constants(&program, true), expected);
+    }
+}
+
+// a pass written as a trampolined function
+struct Normalize {
+    name: &'static str,
+    requires: &'static [Invariant],
+    ensures: &'static [Invariant],
+    rewrite: Rewrite,
+}
+
+impl pipeline::Pass for Normalize {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn requires(&self) -> &[Invariant] {
+        self.requires
+    }
+
+    fn ensures(&self) -> &[Invariant] {
+        self.ensures
+    }
+
+    fn run(&self, arena: &mut Arena, node: Node) -> Node {
+        let rewrite = self.rewrite;
+        trampoline::run(arena, |arena| rewrite(arena, node))
+    }
+}
+
+/// The pipeline of the passes that normalize terms for code generation.
+pub fn pipeline() -> Pipeline {
+    let mut pipeline = Pipeline::new();
+    pipeline.register(Normalize {
+        name: "normalize_app",
+        requires: &[],
+        ensures: &[Invariant::BinderFreeApps],
+        rewrite: normalize_app,
+    });
+    pipeline.register(Normalize {
+        name: "normalize_abs",
+        requires: &[Invariant::BinderFreeApps],
+        ensures: &[Invariant::LetFreeAbsBodies],
+        rewrite: normalize_abs,
+    });
+    pipeline.register(Normalize {
+        name: "normalize_let",
+        requires: &[Invariant::BinderFreeApps, Invariant::LetFreeAbsBodies],
+        ensures: &[Invariant::UnnestedLets],
+        rewrite: normalize_let,
+    });
+    pipeline
+}
+
+#[cfg(test)]
+mod tests_transform_term {
+    use super::*;
+
+    fn transform_term(term: &Term) -> Term {
+        let program = vec![Statement::Declaration {
+            name: Symbol::from("foo"),
+            value: Rc::new(term.clone()),
+            attrs: vec![],
+            doc: None,
+        }];
+        let (mut program, _) = pipeline().run(&program, &[]);
+        match program.pop() {
+            Some(Statement::Declaration {
+                name: _,
+                value,
+                attrs: _,
+                doc: _,
+            }) => Rc::unwrap_or_clone(value),
+            None => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_let_names() {
         let term = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::Var {
-                name: String::from("y"),
+                name: Symbol::from("y"),
             }),
             body: Rc::new(Term::Let {
-                name: String::from("x"),
+                name: Symbol::from("x"),
                 value: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("x"),
+                    name: Symbol::from("x"),
                 }),
             }),
         };
         let expected = Term::Let {
-            name: String::from("x"),
+            name: Symbol::from("x"),
             value: Rc::new(Term::Var {
-                name: String::from("y"),
+                name: Symbol::from("y"),
             }),
             body: Rc::new(Term::Let {
-                name: String::from("x0"),
+                name: Symbol::from("x0"),
                 value: Rc::new(Term::Var {
-                    name: String::from("z"),
+                    name: Symbol::from("z"),
                 }),
                 body: Rc::new(Term::Var {
-                    name: String::from("x0"),
+                    name: Symbol::from("x0"),
                 }),
             }),
         };
-        assert_eq!(mangle(&term, &HashSet::new()), expected);
+        assert_eq!(transform_term(&term), expected);
     }
-}
 
-fn transform_term(term: &Term) -> Term {
-    let term = normalize_app(term);
-    let term = normalize_abs(&term);
-    let term = normalize_let(&term);
-    let term = mangle(&term, &HashSet::new());
-    term
-}
+    #[test]
+    fn test_deep() {
+        // let x0 = y in let x1 = x0 in ... x99999
+        let mut term = Term::Var {
+            name: Symbol::from("x99999"),
+        };
+        for i in (0..100_000).rev() {
+            let value = match i {
+                0 => Symbol::from("y"),
+                i => Symbol::intern(&format!("x{}", i - 1)),
+            };
+            term = Term::Let {
+                name: Symbol::intern(&format!("x{i}")),
+                value: Rc::new(Term::Var { name: value }),
+                body: Rc::new(term),
+            };
+        }
+        assert_eq!(transform_term(&term), term);
 
-fn transform_statement(stmt: &Statement) -> Statement {
-    match stmt {
-        Statement::Declaration { name, value } => Statement::Declaration {
-            name: name.clone(),
-            value: Rc::new(transform_term(value)),
-        },
+        // f (f ... (f (fun x -> x)))
+        let id = Term::Abs {
+            param: Symbol::from("x"),
+            body: Rc::new(Term::Var {
+                name: Symbol::from("x"),
+            }),
+        };
+        let nest = |inner: Term| {
+            let mut term = inner;
+            for _ in 0..100_000 {
+                term = Term::App {
+                    func: Rc::new(Term::Var {
+                        name: Symbol::from("f"),
+                    }),
+                    arg: Rc::new(term),
+                };
+            }
+            term
+        };
+        // let v = fun x -> x in f (f ... (f v))
+        let expected = Term::Let {
+            name: Symbol::from("v"),
+            value: Rc::new(id.clone()),
+            body: Rc::new(nest(Term::Var {
+                name: Symbol::from("v"),
+            })),
+        };
+        assert_eq!(transform_term(&nest(id)), expected);
     }
 }
-pub fn transform(program: &Vec<Statement>) -> Vec<Statement> {
-    program.iter().map(transform_statement).collect()
+
+#[cfg(test)]
+mod tests_pipeline {
+    use super::*;
+    use crate::compiler::parser::parse;
+
+    #[test]
+    fn test_dumps() {
+        let program = parse("let foo = fun x -> x (fun y -> y) (let z = x in z);").unwrap();
+        let (output, dumps) = pipeline().run(&program, &["normalize_app", "normalize_abs"]);
+        let dumps: Vec<_> = dumps
+            .iter()
+            .map(|dump| (dump.pass, dump.program.clone()))
+            .collect();
+        let expected = vec![
+            (
+                "normalize_app",
+                parse("let foo x = let v y = y in let z = x in x v z;").unwrap(),
+            ),
+            (
+                "normalize_abs",
+                parse("let foo = let v y = y in let z x = x in fun x -> x v (z x);").unwrap(),
+            ),
+        ];
+        assert_eq!(dumps, expected);
+        assert_eq!(output, dumps[1].1);
+    }
+
+    #[test]
+    fn test_no_dumps() {
+        let program = parse("let foo = x;").unwrap();
+        let (_, dumps) = pipeline().run(&program, &[]);
+        assert!(dumps.is_empty());
+        assert_eq!(
+            pipeline().pass_names().collect::<Vec<_>>(),
+            ["normalize_app", "normalize_abs", "normalize_let"]
+        );
+    }
 }
@@ -0,0 +1,288 @@
+//! A compiler that translates untyped lambda calculus into TypeScript types.
+//!
+//! [`compile`] runs the whole compiler on a source program.
+//! The stages can also be run one by one: [`parser::parse`] reads a program from the tokens of [`lexer::tokenize`],
+//! [`transformer::fold_constants`] and the passes of [`transformer::pipeline`] transform it,
+//! and [`generator::generate`] writes the TypeScript code.
+//!
+//! With the `wasm` feature, `wasm::compile` is exported to JavaScript by `wasm-bindgen`.
+
+mod compiler;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use compiler::diagnostics::{Diagnostic, Diagnostics, Position};
+
+/// Splitting a source into tokens.
+pub mod lexer {
+    pub use crate::compiler::lexer::{tokenize, Span, Token, TokenKind, Trivia};
+}
+
+/// Reading a program from its source.
+pub mod parser {
+    pub use crate::compiler::parser::{parse, parse_term, parse_with_spans, Span, Spans};
+}
+
+/// Programs, which are sequences of declarations of terms.
+pub mod lambda {
+    pub use crate::compiler::{
+        lambda::{Statement, Term},
+        symbol::Symbol,
+    };
+}
+
+/// Transforming a program before it is generated.
+pub mod transformer {
+    pub use crate::compiler::{
+        pipeline::{Dump, Pipeline},
+        transformer::{evaluate, fold_constants, pipeline},
+    };
+}
+
+/// Translating declarations into combinators.
+pub mod ski {
+    pub use crate::compiler::ski::{translate, Combinators};
+}
+
+/// Writing a program as code.
+pub mod generator {
+    pub use crate::compiler::{
+        cpp::Cpp,
+        generator::{generate, Backend, Names, Runtime, TypeScript},
+        source_map::{Mapped, Mapping, SourceMap},
+    };
+}
+
+/// Exchanging programs with other tools as JSON.
+pub mod ast_json {
+    pub use crate::compiler::ast_json::{read_program, write_program, Error, VERSION};
+}
+
+// what the binaries of this package use besides the API, which is not part of it and may change at any time
+#[doc(hidden)]
+pub mod tools {
+    pub use crate::compiler::{
+        analysis::{analyze, Analysis, Declaration, Occurrence},
+        ast_json::write_dump,
+        formatter::format,
+        printer::DEFAULT_WIDTH,
+        repl::Repl,
+    };
+}
+
+use std::rc::Rc;
+
+use generator::{Backend, Names, SourceMap, TypeScript};
+use lambda::Statement;
+use parser::Spans;
+use transformer::{Dump, Pipeline};
+
+/// Options of [`compile`].
+pub struct CompileOptions {
+    /// Folds declarations marked with `[@eval]`, or all declarations, before transforming them.
+    pub fold_constants: bool,
+    /// Translates declarations marked with `[@ski]`, or all declarations, into combinators.
+    pub ski: bool,
+    /// The passes to run, which are those of [`transformer::pipeline`] by default.
+    pub pipeline: Pipeline,
+    /// The passes after which the program is dumped.
+    pub dump_after: Vec<String>,
+    /// The backend that writes the code, which is [`TypeScript`] by default.
+    pub backend: Box<dyn Backend>,
+    /// Maps the generated code back to the source.
+    pub source_map: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> CompileOptions {
+        CompileOptions {
+            fold_constants: false,
+            ski: false,
+            pipeline: transformer::pipeline(),
+            dump_after: Vec::new(),
+            backend: Box::new(TypeScript::default()),
+            source_map: false,
+        }
+    }
+}
+
+/// The result of [`compile`].
+pub struct Output {
+    /// The generated TypeScript code.
+    pub code: String,
+    /// The program after each pass in `dump_after`, in the order the passes are run.
+    pub dumps: Vec<Dump>,
+    /// The names given to the top-level names in the generated code.
+    pub names: Names,
+    /// The source map of the code if `source_map` is set.
+    pub source_map: Option<SourceMap>,
+}
+
+/// Compiles a source program to TypeScript.
+pub fn compile(source: &str, options: &CompileOptions) -> Result<Output, Diagnostics> {
+    let (program, spans) = parser::parse_with_spans(source)
+        .map_err(|err| Diagnostic::from_parse_error(source, &err))?;
+    compile_with_spans(&program, &spans, source, options)
+}
+
+/// Compiles a program that is already parsed, e.g. one read by [`ast_json::read_program`].
+/// The source map, if any, has no mappings, as the program has no source.
+pub fn compile_program(
+    program: &[Statement],
+    options: &CompileOptions,
+) -> Result<Output, Diagnostics> {
+    compile_with_spans(program, &[], "", options)
+}
+
+// the spans of the declarations of a program rewritten from another one, which is at the end of it.
+// the subterms of a rewritten declaration all come from the original declaration,
+// and the declarations added before the original ones have no spans
+fn rewritten_spans(program: &[Statement], spans: &[Spans], rewritten: &[Statement]) -> Vec<Spans> {
+    let added = rewritten.len() - program.len();
+    let mut res = vec![Vec::new(); added];
+    for (i, (stmt, new_stmt)) in program.iter().zip(&rewritten[added..]).enumerate() {
+        let Statement::Declaration {
+            name: _,
+            value,
+            attrs: _,
+            doc: _,
+        } = stmt;
+        let Statement::Declaration {
+            name: _,
+            value: new_value,
+            attrs: _,
+            doc: _,
+        } = new_stmt;
+        let spans = spans.get(i).map_or(&[][..], Vec::as_slice);
+        res.push(match spans.last() {
+            Some(_) if Rc::ptr_eq(value, new_value) => spans.to_vec(),
+            Some(&span) => vec![span; new_value.size()],
+            None => Vec::new(),
+        });
+    }
+    res
+}
+
+fn compile_with_spans(
+    program: &[Statement],
+    spans: &[Spans],
+    source: &str,
+    options: &CompileOptions,
+) -> Result<Output, Diagnostics> {
+    // terms are traced back to the source only for the source map
+    let spans = if options.source_map { spans } else { &[] };
+    let pipeline = &options.pipeline;
+    let mut errors = Vec::new();
+    let mut dump_after = Vec::new();
+    for pass in &options.dump_after {
+        match pipeline.pass_name(pass) {
+            Ok(name) => dump_after.push(name),
+            Err(err) => errors.push(Diagnostic::new(err)),
+        }
+    }
+    if let Err(err) = pipeline.check(compiler::generator::REQUIRES) {
+        errors.push(Diagnostic::new(err));
+    }
+    if !errors.is_empty() {
+        return Err(Diagnostics(errors));
+    }
+    let folded = transformer::fold_constants(program, options.fold_constants);
+    let spans = rewritten_spans(program, spans, &folded);
+    let (translated, combinators) = ski::translate(&folded, options.ski);
+    let spans = rewritten_spans(&folded, &spans, &translated);
+    let (program, spans, dumps) = pipeline.run_with_spans(&translated, &spans, &dump_after);
+    let (mapped, names) = options
+        .backend
+        .generate_mapped(&program, &spans, combinators.as_ref());
+    let source_map = options.source_map.then(|| SourceMap::new(&mapped, source));
+    Ok(Output {
+        code: mapped.code,
+        dumps,
+        names,
+        source_map,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let source = "let Id x = x; let Two f x = f (f x);";
+        let output = compile(source, &CompileOptions::default()).unwrap();
+        assert!(output.code.contains("type Id$1<x> = x;\n"));
+        assert!(output
+            .code
+            .contains("type Two$2<f, x> = App<f, App<f, x>>;\n"));
+        assert!(output.dumps.is_empty());
+        assert_eq!(output.names.globals[1], ("Two".into(), String::from("Two")));
+
+        let options = CompileOptions {
+            dump_after: vec![String::from("normalize_app")],
+            ..CompileOptions::default()
+        };
+        let output = compile(source, &options).unwrap();
+        assert_eq!(output.dumps.len(), 1);
+        assert_eq!(output.dumps[0].pass, "normalize_app");
+        assert_eq!(output.dumps[0].program, parser::parse(source).unwrap());
+    }
+
+    #[test]
+    fn test_source_map() {
+        let source = "let Id x = x;\nlet F a = let g b = a b in g Id;";
+        let options = CompileOptions {
+            source_map: true,
+            ..CompileOptions::default()
+        };
+        let output = compile(source, &options).unwrap();
+        let source_map = output.source_map.unwrap();
+        let lines: Vec<_> = output.code.lines().collect();
+        let source_lines: Vec<_> = source.lines().collect();
+        let mapped: Vec<_> = source_map
+            .mappings
+            .iter()
+            .map(|mapping| {
+                let code = &lines[mapping.generated_line][mapping.generated_column..];
+                let source = &source_lines[mapping.source_line][mapping.source_column..];
+                (code, source)
+            })
+            .collect();
+        // the helper lifted out of `F` comes from the value of the let
+        assert!(mapped.contains(&(
+            "interface F$g extends Fun { ret: F$g$1<this[\"arg\"]> }",
+            "b = a b in g Id;"
+        )));
+        assert!(mapped.contains(&("type F$g$2<a, b> = App<a, b>;", "a b in g Id;")));
+        assert!(mapped.contains(&("App<a, b>;", "a b in g Id;")));
+        assert!(mapped.contains(&(
+            "interface F extends Fun { ret: F$1<this[\"arg\"]> }",
+            "a = let g b = a b in g Id;"
+        )));
+        assert!(mapped.contains(&("App<F$g, a>, Id>;", "g Id;")));
+
+        let output = compile(source, &CompileOptions::default()).unwrap();
+        assert!(output.source_map.is_none());
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let err = compile("let Id x = x;\nlet = x;", &CompileOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "2:5: syntax error near `=`");
+
+        let mut options = CompileOptions {
+            dump_after: vec![String::from("mangle")],
+            ..CompileOptions::default()
+        };
+        options
+            .pipeline
+            .set_enabled("normalize_app", false)
+            .unwrap();
+        let err = compile("let Id x = x;", &options).err().unwrap();
+        assert_eq!(err.0.len(), 2);
+        assert!(err.0[0].message.starts_with("unknown pass: mangle"));
+        assert!(err.0[1].message.starts_with("pass normalize_abs requires"));
+    }
+}
@@ -1,16 +1,332 @@
-mod compiler;
+use std::{
+    env, fs,
+    io::{self, BufRead, Read, Write},
+    path::Path,
+    process,
+};
 
-use std::io::{self, Read};
+use lambda2ts::{
+    ast_json, compile, compile_program,
+    generator::{Cpp, Runtime, TypeScript},
+    parser,
+    tools::{self, Repl},
+    transformer::Dump,
+    CompileOptions, Diagnostic,
+};
 
-use crate::compiler::{generator, parser, transformer};
+#[derive(Clone, Copy)]
+enum DumpFormat {
+    Text,
+    Json,
+}
 
-fn main() {
+#[derive(Clone, Copy)]
+enum InputFormat {
+    Source,
+    Json,
+}
+
+#[derive(Clone, Copy)]
+enum Emit {
+    Ts,
+    AstJson,
+    Names,
+}
+
+struct Options {
+    input_format: InputFormat,
+    emit: Emit,
+    dump_format: DumpFormat,
+    compile: CompileOptions,
+    // the file to write the source map to, and the name of the source in it
+    source_map: Option<String>,
+    source_name: String,
+}
+
+// the options that take a value, which is written either as `--option=value` or as the next argument
+const VALUE_OPTIONS: &[&str] = &[
+    "--dump-after",
+    "--input-format",
+    "--emit",
+    "--target",
+    "--source-map",
+    "--source-name",
+    "--dump-format",
+    "--enable-pass",
+    "--disable-pass",
+    "--pass-config",
+];
+
+// passes are enabled and disabled in the pipeline as the arguments are read, so that later ones take precedence
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        input_format: InputFormat::Source,
+        emit: Emit::Ts,
+        dump_format: DumpFormat::Text,
+        compile: CompileOptions::default(),
+        source_map: None,
+        source_name: String::from("stdin"),
+    };
+    let pipeline = &mut options.compile.pipeline;
+    // the backend is made after all the arguments are read, as some of them are its options
+    let mut target = String::from("ts");
+    let mut namespaces = false;
+    while let Some(arg) = args.next() {
+        let arg = if VALUE_OPTIONS.contains(&arg.as_str()) {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value of {arg}"))?;
+            format!("{arg}={value}")
+        } else {
+            arg
+        };
+        if let Some(pass) = arg.strip_prefix("--dump-after=") {
+            options.compile.dump_after.push(pass.to_string());
+            continue;
+        }
+        if let Some(format) = arg.strip_prefix("--input-format=") {
+            options.input_format = match format {
+                "source" => InputFormat::Source,
+                "json" => InputFormat::Json,
+                _ => return Err(format!("invalid input format: {format}")),
+            };
+            continue;
+        }
+        if let Some(emit) = arg.strip_prefix("--emit=") {
+            options.emit = match emit {
+                "ts" => Emit::Ts,
+                "ast-json" => Emit::AstJson,
+                "names" => Emit::Names,
+                _ => return Err(format!("invalid output: {emit}")),
+            };
+            continue;
+        }
+        if let Some(name) = arg.strip_prefix("--target=") {
+            target = name.to_string();
+            continue;
+        }
+        if let Some(file) = arg.strip_prefix("--source-map=") {
+            options.source_map = Some(file.to_string());
+            options.compile.source_map = true;
+            continue;
+        }
+        if let Some(name) = arg.strip_prefix("--source-name=") {
+            options.source_name = name.to_string();
+            continue;
+        }
+        if let Some(format) = arg.strip_prefix("--dump-format=") {
+            options.dump_format = match format {
+                "text" => DumpFormat::Text,
+                "json" => DumpFormat::Json,
+                _ => return Err(format!("invalid dump format: {format}")),
+            };
+            continue;
+        }
+        if let Some(pass) = arg.strip_prefix("--enable-pass=") {
+            pipeline.set_enabled(pass, true)?;
+            continue;
+        }
+        if let Some(pass) = arg.strip_prefix("--disable-pass=") {
+            pipeline.set_enabled(pass, false)?;
+            continue;
+        }
+        if let Some(file) = arg.strip_prefix("--pass-config=") {
+            let config = fs::read_to_string(file).map_err(|err| format!("{file}: {err}"))?;
+            pipeline
+                .configure(&config)
+                .map_err(|err| format!("{file}: {err}"))?;
+            continue;
+        }
+        match arg.as_str() {
+            "--fold-constants" => options.compile.fold_constants = true,
+            "--ski" => options.compile.ski = true,
+            "--namespaces" => namespaces = true,
+            "--dump-all" => {
+                options.compile.dump_after = pipeline.pass_names().map(String::from).collect()
+            }
+            _ => return Err(format!("unknown argument: {arg}")),
+        }
+    }
+    options.compile.backend = match target.as_str() {
+        "ts" => Box::new(TypeScript {
+            runtime: Runtime::Local,
+            namespaces,
+        }),
+        "hotscript" => Box::new(TypeScript {
+            runtime: Runtime::Hotscript,
+            namespaces,
+        }),
+        "cpp" if namespaces => {
+            return Err(String::from("--namespaces requires a TypeScript target"))
+        }
+        "cpp" => Box::new(Cpp),
+        _ => return Err(format!("invalid target: {target}")),
+    };
+    Ok(options)
+}
+
+// writes the program after a pass to stderr, so that it does not mix with the generated code
+fn dump(dump: &Dump, format: DumpFormat) {
+    match format {
+        DumpFormat::Text => {
+            eprintln!("(* after {} *)", dump.pass);
+            for stmt in &dump.program {
+                eprintln!("{stmt}");
+            }
+        }
+        DumpFormat::Json => eprintln!("{}", tools::write_dump(dump.pass, &dump.program)),
+    }
+}
+
+struct FmtOptions {
+    width: usize,
+    files: Vec<String>,
+}
+
+fn parse_fmt_args(args: impl Iterator<Item = String>) -> Result<FmtOptions, String> {
+    let mut options = FmtOptions {
+        width: tools::DEFAULT_WIDTH,
+        files: Vec::new(),
+    };
+    for arg in args {
+        if let Some(width) = arg.strip_prefix("--width=") {
+            options.width = width
+                .parse()
+                .map_err(|_| format!("invalid width: {width}"))?;
+        } else if arg.starts_with("--") {
+            return Err(format!("unknown argument: {arg}"));
+        } else {
+            options.files.push(arg);
+        }
+    }
+    Ok(options)
+}
+
+fn read_stdin() -> String {
     let mut input = String::new();
     io::stdin()
         .read_to_string(&mut input)
         .expect("failed to read");
-    let program = parser::parse(&input).expect("parse error");
-    let program = transformer::transform(&program);
-    let code = generator::generate(&program);
-    println!("{}", code);
+    input
+}
+
+// formats the files in place, or stdin to stdout if no files are given
+fn fmt(options: FmtOptions) -> Result<(), String> {
+    if options.files.is_empty() {
+        let input = read_stdin();
+        let output = tools::format(&input, options.width)
+            .map_err(|err| Diagnostic::from_parse_error(&input, &err).to_string())?;
+        print!("{output}");
+        return Ok(());
+    }
+    for file in &options.files {
+        let input = fs::read_to_string(file).map_err(|err| format!("{file}: {err}"))?;
+        let output = tools::format(&input, options.width)
+            .map_err(|err| format!("{file}:{}", Diagnostic::from_parse_error(&input, &err)))?;
+        if output != input {
+            fs::write(file, output).map_err(|err| format!("{file}: {err}"))?;
+        }
+    }
+    Ok(())
+}
+
+// reads a line of input at a time until `:quit` or the end of input
+fn repl() {
+    let mut repl = Repl::new();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().expect("failed to write");
+        let Some(line) = lines.next() else {
+            println!();
+            return;
+        };
+        let line = line.expect("failed to read");
+        if matches!(line.trim(), ":quit" | ":q") {
+            return;
+        }
+        match repl.eval(&line) {
+            Ok(output) => print!("{output}"),
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("fmt") {
+        let options = parse_fmt_args(args.skip(1)).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            process::exit(2);
+        });
+        if let Err(err) = fmt(options) {
+            eprintln!("{err}");
+            process::exit(1);
+        }
+        return;
+    }
+    if args.peek().map(String::as_str) == Some("repl") {
+        repl();
+        return;
+    }
+    let options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(2);
+    });
+    let input = read_stdin();
+    let output = match options.input_format {
+        InputFormat::Source => {
+            // the program is emitted as read, so that other tools can use the parser
+            if let Emit::AstJson = options.emit {
+                match parser::parse(&input) {
+                    Ok(program) => println!("{}", ast_json::write_program(&program)),
+                    Err(err) => {
+                        eprintln!("{}", Diagnostic::from_parse_error(&input, &err));
+                        process::exit(1);
+                    }
+                }
+                return;
+            }
+            compile(&input, &options.compile)
+        }
+        InputFormat::Json => {
+            let program = ast_json::read_program(&input).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                process::exit(1);
+            });
+            if let Emit::AstJson = options.emit {
+                println!("{}", ast_json::write_program(&program));
+                return;
+            }
+            compile_program(&program, &options.compile)
+        }
+    };
+    let output = output.unwrap_or_else(|diagnostics| {
+        eprintln!("{diagnostics}");
+        process::exit(1);
+    });
+    for d in &output.dumps {
+        dump(d, options.dump_format);
+    }
+    match options.emit {
+        Emit::Names => {
+            for (name, new_name) in &output.names.globals {
+                println!("{name} -> {new_name}");
+            }
+        }
+        Emit::Ts | Emit::AstJson => println!("{}", output.code),
+    }
+    if let (Some(file), Some(source_map)) = (&options.source_map, &output.source_map) {
+        // the generated file is named after the source map, which is next to it
+        let map_name = Path::new(file)
+            .file_name()
+            .map_or(file.as_str(), |name| name.to_str().unwrap_or(file));
+        let code_name = map_name.strip_suffix(".map").unwrap_or(map_name);
+        let json = source_map.to_json(code_name, &options.source_name);
+        if let Err(err) = fs::write(file, json) {
+            eprintln!("{file}: {err}");
+            process::exit(1);
+        }
+        println!("//# sourceMappingURL={map_name}");
+    }
 }
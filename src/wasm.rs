@@ -0,0 +1,98 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{compile as compile_source, compiler::diagnostics, CompileOptions};
+
+/// An error found while compiling, with the line and the column counted from 1 if it has a position.
+#[wasm_bindgen]
+pub struct Diagnostic {
+    message: String,
+    line: Option<u32>,
+    column: Option<u32>,
+}
+
+#[wasm_bindgen]
+impl Diagnostic {
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn column(&self) -> Option<u32> {
+        self.column
+    }
+}
+
+impl From<&diagnostics::Diagnostic> for Diagnostic {
+    fn from(diagnostic: &diagnostics::Diagnostic) -> Diagnostic {
+        Diagnostic {
+            message: diagnostic.message.clone(),
+            line: diagnostic.position.map(|position| position.line as u32),
+            column: diagnostic.position.map(|position| position.column as u32),
+        }
+    }
+}
+
+/// The generated code if compiling succeeded, and the diagnostics otherwise.
+#[wasm_bindgen]
+pub struct CompileResult {
+    code: Option<String>,
+    diagnostics: Vec<diagnostics::Diagnostic>,
+}
+
+#[wasm_bindgen]
+impl CompileResult {
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> Option<String> {
+        self.code.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.iter().map(Diagnostic::from).collect()
+    }
+}
+
+/// Compiles a source program to TypeScript.
+#[wasm_bindgen]
+pub fn compile(source: &str, fold_constants: bool) -> CompileResult {
+    let options = CompileOptions {
+        fold_constants,
+        ..CompileOptions::default()
+    };
+    match compile_source(source, &options) {
+        Ok(output) => CompileResult {
+            code: Some(output.code),
+            diagnostics: Vec::new(),
+        },
+        Err(diagnostics) => CompileResult {
+            code: None,
+            diagnostics: diagnostics.0,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let result = compile("let Id x = x;", false);
+        assert!(result.code().unwrap().contains("type Id$1<x> = x;\n"));
+        assert!(result.diagnostics().is_empty());
+
+        let result = compile("let Id x = x;\nlet = x;", false);
+        assert!(result.code().is_none());
+        let diagnostics = result.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message(), "syntax error near `=`");
+        assert_eq!(diagnostics[0].line(), Some(2));
+        assert_eq!(diagnostics[0].column(), Some(5));
+    }
+}
@@ -0,0 +1,21 @@
+// runs the entry point in a headless browser with `wasm-pack test --headless --firefox -- --features wasm`
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use lambda2ts::wasm::compile;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn test_compile() {
+    let result = compile("let Id x = x;", false);
+    assert!(result.code().unwrap().contains("type Id$1<x> = x;\n"));
+    assert!(result.diagnostics().is_empty());
+}
+
+#[wasm_bindgen_test]
+fn test_compile_error() {
+    let result = compile("let = x;", false);
+    assert!(result.code().is_none());
+    assert_eq!(result.diagnostics()[0].line(), Some(1));
+}